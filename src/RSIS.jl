@@ -90,6 +90,7 @@ using .MSignalIO
 export logsignal, logsignalfile, listlogged, getlogdata
 export setlogfilelimit, generate_log_structures
 export readsignal, generate_read_structures
+export exportsignal, listexported, generate_export_structures
 
 # final global variables
 
@@ -101,6 +102,7 @@ function __init__() :: Nothing
     # Add callbacks for supported utilities
     register_scheduler_callback(generate_log_structures, 10)
     register_scheduler_callback(generate_read_structures, 11)
+    register_scheduler_callback(generate_export_structures, 10, afterthreads = true)
 end
 
 
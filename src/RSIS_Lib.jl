@@ -5,12 +5,16 @@ using Base: Int32, _getmeta
 export LoadLibrary, UnloadLibrary, InitLibrary, ShutdownLibrary
 export newmodel, deletemodel!, getmodel, listmodels, listmodelsbytag, listlibraries
 export addcapp
-export getscheduler, initscheduler, stepscheduler, endscheduler, schedulerparam!
-export addthread, schedulemodel, createconnection
+export getscheduler, initscheduler, stepscheduler, endscheduler, resetscheduler, shutdownscheduler, schedulerparam!, waitforstate
+export getschedulerparam, listschedulerparams, setschedulerclock, listchannels
+export startrecording, stoprecording, addreplay, setchannellatency, addbridge
+export addsignalexport
+export addthread, schedulemodel, insertmodel, movemodel, removemodel, createconnection
+export setmodelenabled, getmodelenabled, setmodelrate
 export LoadModelLib, UnloadModelLib, _libraryprefix, _libraryextension
 export _getmodelinstance, _meta_get, _meta_set, _get_ptr
 export ModelInstance, CFunctionInstance, ModelReference
-export simstatus, SchedulerState, CONFIG, INITIALIZING, INITIALIZED, RUNNING, PAUSED, ENDING, ENDED, ERRORED
+export simstatus, schedulerfaults, SchedulerState, CONFIG, INITIALIZING, INITIALIZED, RUNNING, PAUSED, ENDING, ENDED, ERRORED, TIMEOUT
 export capp_getnmeta, capp_getmeta
 export SignalTypes, INPUT, OUTPUT, DATA, PARAM
 
@@ -30,6 +34,7 @@ using ..MsgPack
     ENDING=5
     ENDED=6
     ERRORED=7
+    TIMEOUT=8
 end
 
 # globals
@@ -42,6 +47,9 @@ _namespaces = Dict{String, Vector{String}}()
     ERR = 1
 end
 
+# returned by calls that time out, matches RSISStat::TIMEOUT in rust
+const STAT_TIMEOUT = 10
+
 @enum AppType::Int32 begin
     AT_SO = 0
     AT_CF = 1
@@ -71,6 +79,32 @@ mutable struct LibFuncs
     s_getstate
     s_getschedulername
     s_configscheduler
+    s_getlasterror
+    s_stepschedulerblocking
+    s_waitforstate
+    s_getfaultcount
+    s_getfault
+    s_getschedulerconfig
+    s_listschedulerconfig
+    s_setclockmonotonic
+    s_setclocktai
+    s_setclocksocket
+    s_setclockfd
+    s_listchannels
+    s_startrecording
+    s_stoprecording
+    s_addreplay
+    s_setchannellatency
+    s_addbridge
+    s_addsignalexport
+    s_insertmodelat
+    s_movemodel
+    s_removemodelbyid
+    s_setmodelenabled
+    s_getmodelenabled
+    s_setmodelrate
+    s_resetscheduler
+    s_shutdownscheduler
     function LibFuncs(lib)
         new(Libdl.dlsym(lib, :library_initialize),
             Libdl.dlsym(lib, :library_shutdown),
@@ -87,7 +121,33 @@ mutable struct LibFuncs
             Libdl.dlsym(lib, :get_message),
             Libdl.dlsym(lib, :get_scheduler_state),
             Libdl.dlsym(lib, :get_scheduler_name),
-            Libdl.dlsym(lib, :config_scheduler))
+            Libdl.dlsym(lib, :config_scheduler),
+            Libdl.dlsym(lib, :get_last_error),
+            Libdl.dlsym(lib, :step_scheduler_blocking),
+            Libdl.dlsym(lib, :wait_for_state),
+            Libdl.dlsym(lib, :get_fault_count),
+            Libdl.dlsym(lib, :get_fault),
+            Libdl.dlsym(lib, :get_scheduler_config),
+            Libdl.dlsym(lib, :list_scheduler_config),
+            Libdl.dlsym(lib, :set_clock_monotonic),
+            Libdl.dlsym(lib, :set_clock_tai),
+            Libdl.dlsym(lib, :set_clock_socket),
+            Libdl.dlsym(lib, :set_clock_fd),
+            Libdl.dlsym(lib, :list_channels),
+            Libdl.dlsym(lib, :start_recording),
+            Libdl.dlsym(lib, :stop_recording),
+            Libdl.dlsym(lib, :add_replay),
+            Libdl.dlsym(lib, :set_channel_latency),
+            Libdl.dlsym(lib, :add_bridge),
+            Libdl.dlsym(lib, :add_signal_export),
+            Libdl.dlsym(lib, :insert_model_at),
+            Libdl.dlsym(lib, :move_model),
+            Libdl.dlsym(lib, :remove_model_by_id),
+            Libdl.dlsym(lib, :set_model_enabled),
+            Libdl.dlsym(lib, :get_model_enabled),
+            Libdl.dlsym(lib, :set_model_rate),
+            Libdl.dlsym(lib, :reset_scheduler),
+            Libdl.dlsym(lib, :shutdown_scheduler))
     end
 end
 
@@ -504,29 +564,122 @@ function addthread(frequency::Float64)
     end
 end
 
-function schedulemodel(model::ModelReference, thread::Int64, divisor::Int64, offset::Int64) :: Nothing
+"""
+    schedulemodel(model::ModelReference, thread::Int64, divisor::Int64, offset::Int64)
+Hands a model to the core library and returns the stable id it was scheduled with.
+"""
+function schedulemodel(model::ModelReference, thread::Int64, divisor::Int64, offset::Int64) :: UInt64
     _app = _getmodelinstance(model)
+    id = Ref{UInt64}(0)
     if isa(_app, ModelInstance)
         # the framework moves the object around, get the new pointer
         newptr = ccall(_sym.s_addmodel,
             Ptr{Cvoid},
-            (Int64, Ptr{Cvoid}, Int64, Int64),
-            thread, _app.obj, divisor, offset)
+            (Int64, Ptr{Cvoid}, Int64, Int64, Ref{UInt64}),
+            thread, _app.obj, divisor, offset, id)
         if newptr == 0
-            throw(ErrorException("Call to `add_model` in library failed"))
+            throw(ErrorException("Call to `add_model` in library failed with error $(ccall(_sym.s_getlasterror, UInt32, ()))"))
         end
         _app.obj = newptr
     else
         # CFunctionInstance
         newptr = ccall(_sym.s_addmodel_by_callbacks,
             Ptr{Cvoid},
-            (Int64, Ptr{Cvoid}, Ptr{Cvoid}, Ptr{Cvoid}, Ptr{Cvoid}, Ptr{Cvoid}, Ptr{Cvoid}, Ptr{Cvoid}, Int64, Int64),
-            thread, _app.obj, _app.config_func, _app.init_func, _app.step_func, _app.pause_func, _app.stop_func, _app.destructor, divisor, offset)
+            (Int64, Ptr{Cvoid}, Ptr{Cvoid}, Ptr{Cvoid}, Ptr{Cvoid}, Ptr{Cvoid}, Ptr{Cvoid}, Ptr{Cvoid}, Int64, Int64, Ref{UInt64}),
+            thread, _app.obj, _app.config_func, _app.init_func, _app.step_func, _app.pause_func, _app.stop_func, _app.destructor, divisor, offset, id)
         if newptr == 0
-            throw(ErrorException("Call to `add_model_by_callbacks` in library failed"))
+            throw(ErrorException("Call to `add_model_by_callbacks` in library failed with error $(ccall(_sym.s_getlasterror, UInt32, ()))"))
         end
         _app.obj = newptr
     end
+    return id[]
+end
+
+"""
+    insertmodel(model::ModelReference, thread::Int64, index::Int64, divisor::Int64, offset::Int64)
+Hands a model to the core library at a position in the thread's run order,
+and returns the stable id it was scheduled with. Only models loaded from a
+library can be inserted
+"""
+function insertmodel(model::ModelReference, thread::Int64, index::Int64, divisor::Int64, offset::Int64) :: UInt64
+    _app = _getmodelinstance(model)
+    if !isa(_app, ModelInstance)
+        throw(ErrorException("Only models loaded from a library can be inserted at an index"))
+    end
+    id = Ref{UInt64}(0)
+    # the framework moves the object around, get the new pointer
+    newptr = ccall(_sym.s_insertmodelat,
+        Ptr{Cvoid},
+        (Int64, Int64, Ptr{Cvoid}, Int64, Int64, Ref{UInt64}),
+        thread, index, _app.obj, divisor, offset, id)
+    if newptr == 0
+        throw(ErrorException("Call to `insert_model_at` in library failed with error $(ccall(_sym.s_getlasterror, UInt32, ()))"))
+    end
+    _app.obj = newptr
+    return id[]
+end
+
+"""
+    movemodel(id::UInt64, thread::Int64, index::Int64)
+Moves a scheduled model to a position in the run order of a thread. Only
+permitted before the scheduler is initialized
+"""
+function movemodel(id::UInt64, thread::Int64, index::Int64) :: Nothing
+    stat = ccall(_sym.s_movemodel, UInt32, (UInt64, Int64, Int64), id, thread, index)
+    if stat != 0
+        throw(ErrorException("Call to `move_model` in library failed with error: $(stat)"))
+    end
+    return
+end
+
+"""
+    removemodel(id::UInt64)
+Removes a scheduled model from its thread, destroying it. Only permitted
+before the scheduler is initialized
+"""
+function removemodel(id::UInt64) :: Nothing
+    stat = ccall(_sym.s_removemodelbyid, UInt32, (UInt64,), id)
+    if stat != 0
+        throw(ErrorException("Call to `remove_model_by_id` in library failed with error: $(stat)"))
+    end
+    return
+end
+
+"""
+    setmodelenabled(id::UInt64, enabled::Bool)
+Enables or disables a scheduled model. A disabled model is skipped by its
+thread, starting at the next frame
+"""
+function setmodelenabled(id::UInt64, enabled::Bool) :: Nothing
+    stat = ccall(_sym.s_setmodelenabled, UInt32, (UInt64, UInt32), id, enabled)
+    if stat != 0
+        throw(ErrorException("Call to `set_model_enabled` in library failed with error: $(stat)"))
+    end
+    return
+end
+
+"""
+    getmodelenabled(id::UInt64)
+Returns whether a scheduled model is enabled, as of the latest request
+"""
+function getmodelenabled(id::UInt64) :: Bool
+    stat = ccall(_sym.s_getmodelenabled, Int32, (UInt64,), id)
+    if stat < 0
+        throw(ErrorException("Call to `get_model_enabled` in library failed, no model with id $(id)"))
+    end
+    return stat == 1
+end
+
+"""
+    setmodelrate(id::UInt64, divisor::Int64, offset::Int64)
+Changes the rate group and phase of a scheduled model. Only permitted before
+the simulation runs, or while it is paused
+"""
+function setmodelrate(id::UInt64, divisor::Int64, offset::Int64) :: Nothing
+    stat = ccall(_sym.s_setmodelrate, UInt32, (UInt64, Int64, Int64), id, divisor, offset)
+    if stat != 0
+        throw(ErrorException("Call to `set_model_rate` in library failed with error: $(stat)"))
+    end
     return
 end
 
@@ -552,6 +705,33 @@ function stepscheduler(steps::UInt64) :: Nothing
     end
 end
 
+"""
+    stepscheduler(steps::UInt64, timeout::Float64)
+Steps the scheduler, returning once the step has completed. Returns false if
+the scheduler ended or errored instead of pausing. A negative timeout waits
+forever.
+"""
+function stepscheduler(steps::UInt64, timeout::Float64) :: Bool
+    stat = ccall(_sym.s_stepschedulerblocking, UInt32, (UInt64, Float64), steps, timeout);
+    if stat == STAT_TIMEOUT
+        throw(ErrorException("Call to step_scheduler_blocking timed out after $(timeout) [s]"))
+    end
+    return stat == 0
+end
+
+"""
+    waitforstate(state::SchedulerState; timeout::Float64 = -1.0)
+Blocks until the scheduler reaches the specified state. Returns false if the
+scheduler ended or errored instead. A negative timeout waits forever.
+"""
+function waitforstate(state::SchedulerState; timeout::Float64 = -1.0) :: Bool
+    stat = ccall(_sym.s_waitforstate, UInt32, (Int32, Float64), Int32(state), timeout);
+    if stat == STAT_TIMEOUT
+        throw(ErrorException("Call to wait_for_state timed out after $(timeout) [s]"))
+    end
+    return stat == 0
+end
+
 function endscheduler() :: Nothing
     stat = ccall(_sym.s_endscheduler, UInt32, ());
     if stat != 0
@@ -559,6 +739,35 @@ function endscheduler() :: Nothing
     end
 end
 
+"""
+    shutdownscheduler(; timeout::Float64 = -1.0)
+Ends the simulation, stopping every model, and waits for all threads to exit.
+A negative timeout waits forever
+"""
+function shutdownscheduler(; timeout::Float64 = -1.0) :: Nothing
+    stat = ccall(_sym.s_shutdownscheduler, UInt32, (Float64,), timeout)
+    if stat == STAT_TIMEOUT
+        throw(ErrorException("Call to `shutdown_scheduler` timed out after $(timeout) [s]"))
+    elseif stat != 0
+        throw(ErrorException("Call to `shutdown_scheduler` in library failed with error: $(stat)"))
+    end
+    return
+end
+
+"""
+    resetscheduler()
+Ends the simulation if it is still running, joins all threads, and returns
+the scheduler and its models to the CONFIG state so that it can be
+initialized again
+"""
+function resetscheduler() :: Nothing
+    stat = ccall(_sym.s_resetscheduler, UInt32, ())
+    if stat != 0
+        throw(ErrorException("Call to `reset_scheduler` in library failed with error: $(stat)"))
+    end
+    return
+end
+
 """
     simstatus()
 Returns the current state of the simulation
@@ -568,6 +777,22 @@ function simstatus() :: SchedulerState
     return SchedulerState(stat);
 end
 
+"""
+    schedulerfaults()
+Returns a description of each model panic caught by the scheduler since it
+was last reset
+"""
+function schedulerfaults() :: Vector{String}
+    faults = Vector{String}()
+    count = ccall(_sym.s_getfaultcount, UInt32, ())
+    for i in 1:count
+        if ccall(_sym.s_getfault, UInt32, (UInt32,), i - 1) == 0
+            push!(faults, unsafe_string(ccall(_sym.s_getmessage, Cstring, ())))
+        end
+    end
+    return faults
+end
+
 """
     schedulerparam!(name::String, parameter)
 Sends a configuration key value pair to the underlying selected
@@ -584,4 +809,194 @@ function schedulerparam!(name::String, parameter) :: Nothing
     end
 end
 
+"""
+    setschedulerclock(source::Symbol; path::String = "", fd::Int = -1, period::Float64 = 0.0)
+Selects the clock that frames are paced against. `source` is one of
+- `:monotonic` the host monotonic clock, the default
+- `:tai` the host CLOCK_TAI clock, Linux only
+- `:socket` ticks from a master listening on the UNIX socket at `path`
+- `:fd` ticks read from the open file descriptor `fd`, e.g. a timerfd
+External ticks are native endian UInt64 counts, each advancing the clock by
+`period` seconds. Selecting a clock turns on pacing at a real-time scale of
+1 if no scale is set.
+```jldoctest
+julia> setschedulerclock(:socket, path = "/tmp/master.sock", period = 0.01)
+```
+"""
+function setschedulerclock(source::Symbol; path::String = "", fd::Int = -1, period::Float64 = 0.0) :: Nothing
+    if source == :monotonic
+        stat = ccall(_sym.s_setclockmonotonic, UInt32, ())
+    elseif source == :tai
+        stat = ccall(_sym.s_setclocktai, UInt32, ())
+    elseif source == :socket
+        stat = ccall(_sym.s_setclocksocket, UInt32, (Cstring, Float64), path, period)
+    elseif source == :fd
+        stat = ccall(_sym.s_setclockfd, UInt32, (Int32, Float64), Int32(fd), period)
+    else
+        throw(ArgumentError("Unknown clock source $(source)"))
+    end
+    if stat != 0
+        throw(ErrorException("Failed to set $(source) clock source with error: $(stat)"))
+    end
+end
+
+"""
+    startrecording(path::String)
+Records every message sent on a channel, with its sim tick and sender, to a
+log file until `stoprecording` is called
+"""
+function startrecording(path::String) :: Nothing
+    stat = ccall(_sym.s_startrecording, UInt32, (Cstring,), path)
+    if stat != 0
+        throw(ErrorException("Call to `start_recording` in library failed with error: $(stat)"))
+    end
+end
+
+function stoprecording() :: Nothing
+    stat = ccall(_sym.s_stoprecording, UInt32, ())
+    if stat != 0
+        throw(ErrorException("Call to `stop_recording` in library failed with error: $(stat)"))
+    end
+end
+
+"""
+    addreplay(path::String, channel::Union{String, Int64}, thread::Int64, divisor::Int64, offset::Int64)
+Schedules a model that sends the messages recorded on a channel back into
+it, and returns the id it was scheduled with
+"""
+function addreplay(path::String, channel::Union{String, Int64}, thread::Int64, divisor::Int64, offset::Int64) :: UInt64
+    id = Ref{UInt64}(0)
+    if isa(channel, String)
+        stat = ccall(_sym.s_addreplay, UInt32, (Cstring, Cstring, Int64, Int64, Int64, Int64, Ref{UInt64}),
+            path, channel, 0, thread, divisor, offset, id)
+    else
+        stat = ccall(_sym.s_addreplay, UInt32, (Cstring, Ptr{Cchar}, Int64, Int64, Int64, Int64, Ref{UInt64}),
+            path, C_NULL, channel, thread, divisor, offset, id)
+    end
+    if stat != 0
+        throw(ErrorException("Call to `add_replay` in library failed with error: $(stat)"))
+    end
+    return id[]
+end
+
+"""
+    addbridge(channel::String, schema::Dict, transport::Symbol, direction::Symbol, thread::Int64, divisor::Int64, offset::Int64; bind::String = "", peer::String = "", sample::Bool = false)
+Schedules a model that connects a named channel to a socket, and returns the
+id it was scheduled with. `transport` is `:unix` for a UNIX datagram socket
+or `:udp`. An `:inbound` bridge sends the frames received at `bind` into the
+channel, and an `:outbound` bridge sends the messages on the channel to
+`peer`. Each datagram is one MessagePack map with the keys `channel`, `tick`,
+`time`, `element`, `element_size` and `data`. `schema` is given in the form
+returned by `listchannels`
+```jldoctest
+julia> addbridge("nav.state", Dict("ARRAY" => Dict("element" => "f64", "shape" => [3])), :udp, :outbound, 0, 1, 0, peer = "127.0.0.1:5005")
+```
+"""
+function addbridge(channel::String, schema::Dict, transport::Symbol, direction::Symbol, thread::Int64, divisor::Int64, offset::Int64;
+                   bind::String = "", peer::String = "", sample::Bool = false) :: UInt64
+    config = Dict("channel" => channel, "schema" => schema, "sample" => sample,
+                  "transport" => uppercase(String(transport)), "direction" => uppercase(String(direction)),
+                  "bind" => bind, "peer" => peer)
+    configpack = pack(config)
+    configdata = BufferData(pointer(configpack), length(configpack))
+    id = Ref{UInt64}(0)
+    stat = ccall(_sym.s_addbridge, UInt32, (BufferData, Int64, Int64, Int64, Ref{UInt64}),
+        configdata, thread, divisor, offset, id)
+    if stat != 0
+        throw(ErrorException("Call to `add_bridge` in library failed with error: $(stat)"))
+    end
+    return id[]
+end
+
+"""
+    addsignalexport(segment::String, thread::Int64, signals::Vector{Dict{String, Any}})
+Creates a POSIX shared memory segment that the signals are copied into at
+the end of every frame of a thread. Each signal is a Dict with the keys
+`name`, `element` (its type name), `address` and `size` in bytes
+"""
+function addsignalexport(segment::String, thread::Int64, signals::Vector{Dict{String, Any}}) :: Nothing
+    signalpack = pack(signals)
+    signaldata = BufferData(pointer(signalpack), length(signalpack))
+    stat = ccall(_sym.s_addsignalexport, UInt32, (Cstring, Int64, BufferData), segment, thread, signaldata)
+    if stat != 0
+        throw(ErrorException("Call to `add_signal_export` in library failed with error: $(stat)"))
+    end
+end
+
+"""
+    setchannellatency(channel::Union{String, Int64}, latency::Float64)
+Delays the messages on a channel, so that a message sent at sim time t is
+received from the first frame at or after t + latency, whichever thread it
+was sent from. Messages from other threads are only received once the threads
+have caught up with each other, at the hyperperiod or between steps, so they
+may arrive later than t + latency
+"""
+function setchannellatency(channel::Union{String, Int64}, latency::Float64) :: Nothing
+    if isa(channel, String)
+        stat = ccall(_sym.s_setchannellatency, UInt32, (Cstring, Int64, Float64), channel, 0, latency)
+    else
+        stat = ccall(_sym.s_setchannellatency, UInt32, (Ptr{Cchar}, Int64, Float64), C_NULL, channel, latency)
+    end
+    if stat != 0
+        throw(ErrorException("Call to `set_channel_latency` in library failed with error: $(stat)"))
+    end
+end
+
+# filled by the library with MessagePack data
+_return_buffer = Vector{UInt8}()
+function _setup_return_buffer(size::UInt) :: Ptr{UInt8}
+    global _return_buffer
+    _return_buffer = zeros(UInt8, size)
+    pointer(_return_buffer)
+end
+
+"""
+    getschedulerparam(name::String)
+Returns the current value of a scheduler configuration key
+"""
+function getschedulerparam(name::String) :: Any
+    keypack = pack(name)
+    keydata = BufferData(pointer(keypack), length(keypack))
+    stat = ccall(_sym.s_getschedulerconfig, UInt32, (BufferData, Ptr{Cvoid}), keydata,
+        @cfunction(_setup_return_buffer, Ptr{UInt8}, (UInt,)))
+    if stat != 0
+        throw(ErrorException("Call to `get_scheduler_config` in library failed with error: $(stat)"))
+    end
+    return unpack(_return_buffer)
+end
+
+"""
+    listschedulerparams()
+Returns every scheduler configuration key, with its type, default value,
+current value, and the scheduler states in which it can be set
+```jldoctest
+julia> for p in listschedulerparams(); println(p["name"], ": ", p["doc"]); end
+```
+"""
+function listschedulerparams() :: Vector{Dict{String, Any}}
+    stat = ccall(_sym.s_listschedulerconfig, UInt32, (Ptr{Cvoid},),
+        @cfunction(_setup_return_buffer, Ptr{UInt8}, (UInt,)))
+    if stat != 0
+        throw(ErrorException("Call to `list_scheduler_config` in library failed with error: $(stat)"))
+    end
+    return [Dict{String, Any}(entry) for entry in unpack(_return_buffer)]
+end
+
+"""
+    listchannels()
+Returns every message channel, with its kind ("queue" or "sample"), its
+registered schema, the ids of the models that publish and subscribe to it,
+the number of messages dropped by full subscriber queues, and its latency.
+`senders` and `receivers` count the open ends of the channel, and `queues`
+lists the depth, queued message count and overflow policy of each receiver
+"""
+function listchannels() :: Vector{Dict{String, Any}}
+    stat = ccall(_sym.s_listchannels, UInt32, (Ptr{Cvoid},),
+        @cfunction(_setup_return_buffer, Ptr{UInt8}, (UInt,)))
+    if stat != 0
+        throw(ErrorException("Call to `list_channels` in library failed with error: $(stat)"))
+    end
+    return [Dict{String, Any}(entry) for entry in unpack(_return_buffer)]
+end
+
 end
@@ -8,7 +8,7 @@ using ..DataFrames
 
 export setthread, setnumthreads, schedule, threadinfo, scheduleinfo
 export initsim, stepsim, endsim, setstoptime, settimelimit, gettimelimit
-export getstoptime
+export getstoptime, setrealtimescale
 export register_scheduler_callback
 
 mutable struct SModel
@@ -39,6 +39,7 @@ _max_sim_duration = Float64(-1)
 _time_limits = Dict{String, Float64}()
 
 _callbacks = Dict{Int, Vector{Function}}()
+_thread_callbacks = Dict{Int, Vector{Function}}() # called once threads exist
 
 function _resetthreads() :: Nothing
     empty!(_threads)
@@ -223,6 +224,11 @@ function initsim(;block::Bool = false) :: Nothing
             schedulemodel(model.ref, i - 1, Int64(thread.frequency / model.frequency), model.offset)
         end
     end
+    for key in sort!(collect(keys(_thread_callbacks)))
+        for cb in _thread_callbacks[key]
+            cb()
+        end
+    end
     initscheduler()
 
     _steptime_start = 0 # index
@@ -234,9 +240,7 @@ function initsim(;block::Bool = false) :: Nothing
     end
 
     if block
-        while simstatus() == INITIALIZING
-            sleep(0.1) # seconds
-        end
+        waitforstate(INITIALIZED)
     end
 end
 
@@ -264,21 +268,11 @@ function stepsim(steps::Int64 = 1; blocking::Bool = false)
     _steptime_start += steps
 
     # call into the core library
-    stepscheduler(UInt64(steps));
-
-    # if set to block, don't return until we're not RUNNING anymore
+    # if set to block, don't return until the step has completed
     if blocking
-        while true
-            stat = simstatus()
-            if stat == INITIALIZED || stat == PAUSED
-                sleep(0.1) # seconds
-            else
-                break
-            end
-        end
-        while simstatus() == RUNNING
-            sleep(0.1) # seconds
-        end
+        stepscheduler(UInt64(steps), -1.0);
+    else
+        stepscheduler(UInt64(steps));
     end
 end
 
@@ -388,17 +382,38 @@ function gettimelimit(name::String) :: Float64
 end
 
 """
-    register_scheduler_callback(cb::Function, priority::Int)
+    setrealtimescale(scale::Real)
+Paces the simulation at `scale` sim seconds per wall clock second. A scale
+of zero or `Inf` runs the simulation as fast as possible. Can be changed
+while the simulation is paused.
+```jldoctest
+julia> setrealtimescale(10) # fast forward
+julia> setrealtimescale(0.5)
+```
+"""
+function setrealtimescale(scale::Real) :: Nothing
+    if scale < 0
+        throw(ArgumentError("Real-time scale: $scale is negative"))
+    end
+    schedulerparam!("rtscale", Float64(scale))
+end
+
+"""
+    register_scheduler_callback(cb::Function, priority::Int; afterthreads::Bool = false)
 Registers a callback to call during initsim.
 
-Used for allowing modules to create model on the fly.
+Used for allowing modules to create model on the fly. Callbacks with
+`afterthreads` set are called once threads have been created and models
+scheduled, for utilities that attach to threads.
 """
-function register_scheduler_callback(cb::Function, priority::Int) :: Nothing
+function register_scheduler_callback(cb::Function, priority::Int; afterthreads::Bool = false) :: Nothing
     global _callbacks
-    if !(priority in keys(_callbacks))
-        _callbacks[priority] = Vector{Function}()
+    global _thread_callbacks
+    callbacks = afterthreads ? _thread_callbacks : _callbacks
+    if !(priority in keys(callbacks))
+        callbacks[priority] = Vector{Function}()
     end
-    push!(_callbacks[priority], cb);
+    push!(callbacks[priority], cb);
     return
 end
 
@@ -15,9 +15,11 @@ export logsignal, logsignalfile, listlogged
 export setlogfilelimit, generate_log_structures
 export getlogdata
 export readsignal, generate_read_structures
+export exportsignal, listexported, generate_export_structures
 
 _loggedfields = Dict{String, Dict{String, Float64}}()
 _readfields   = Dict{String, Dict{String, Float64}}()
+_exportedfields = Dict{String, Vector{Tuple{String, String}}}() # segment => (model, port)
 
 _log_file_size = 100 # 100 MB
 
@@ -317,6 +319,87 @@ function generate_read_structures()
     settimelimit("datareader", max_time)
 end
 
+"""
+    exportsignal(model::ModelReference, path::String; segment::String = "rsis")
+Publishes a signal to a POSIX shared memory segment, updated at the end of
+every frame of the thread the model is scheduled on, so that tools on the
+same machine can read it while the sim runs. Every signal exported to a
+segment must come from models on the same thread.
+```jldoctest
+julia> exportsignal(m, "outputs.position", segment = "nav")
+```
+"""
+function exportsignal(model::String, path::String; segment::String = "rsis") :: Nothing
+    if !(segment in keys(_exportedfields))
+        _exportedfields[segment] = Vector{Tuple{String, String}}()
+    end
+    push!(_exportedfields[segment], (model, path))
+    return
+end
+
+function exportsignal(model::ModelReference, path::String; segment::String = "rsis") :: Nothing
+    exportsignal(model.name, path, segment = segment)
+end
+
+"""
+    listexported()
+Returns all signals currently being exported to shared memory
+"""
+function listexported()
+    exported = DataFrame("segment" => Vector{String}(), "model" => Vector{String}(), "port" => Vector{String}())
+    for (segment, fields) in _exportedfields
+        for (app, location) in fields
+            push!(exported, (segment, app, location))
+        end
+    end
+    return exported
+end
+
+function generate_export_structures()
+    if length(keys(_exportedfields)) == 0
+        return
+    end
+
+    # make a map of modelreferences to threads
+    mapping = Dict{String, Int}()
+    tinfo = threadinfo()
+    for i in 1:nrow(tinfo)
+        s = scheduleinfo(i)
+        for (m, f) in eachrow(s)
+            mapping[m.name] = i
+        end
+    end
+
+    for (segment, fields) in _exportedfields
+        thread = -1
+        signals = Vector{Dict{String, Any}}()
+        for (app, location) in fields
+            if !(app in keys(mapping))
+                @warn "App: $app not scheduled. Skipping exported signal $location"
+                continue
+            end
+            if thread != -1 && mapping[app] != thread
+                @warn "App: $app is not on Thread $thread with the rest of segment $segment. Skipping exported signal $location"
+                continue
+            end
+            thread = mapping[app]
+            modelinst = _getmodelinstance(ModelReference(app))
+            (indices, port) = _parselocation(modelinst, location)
+            push!(signals, Dict{String, Any}(
+                "name" => "$app.$location",
+                "element" => port.type,
+                "address" => UInt64(_get_ptr(modelinst, indices)),
+                "size" => UInt64(sizeof(_gettype(port.type)) * prod(port.dimension))))
+        end
+        if isempty(signals)
+            continue
+        end
+        # Convert 1 based indexing to 0 based indexing for the thread id
+        addsignalexport(segment, thread - 1, signals)
+        @info "Exporting $(length(signals)) signals to shared memory segment $segment on Thread $thread"
+    end
+end
+
 """
     getlogdata()
 Returns references to allocated memory storing logged data
@@ -0,0 +1,518 @@
+
+extern crate rmp_serde as rmps;
+
+// Out-of-process channel bridges. A bridge connects one named channel to a
+// UNIX datagram or UDP socket, so that external tools or another RSIS
+// process can exchange messages with running models. Every datagram holds a
+// single MessagePack map, a Frame, carrying one message of the channel
+
+use data_buffer::DataBuffer;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{SeqAccess, Visitor};
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::{self as unix, UnixDatagram};
+
+use rsisappinterface::BaseModel;
+use rsisappinterface::BufferStruct;
+use rsisappinterface::ChannelRx;
+use rsisappinterface::ChannelTx;
+use rsisappinterface::ConfigStatus;
+use rsisappinterface::Framework;
+use rsisappinterface::RuntimeStatus;
+use rsisappinterface::SizeCallback;
+
+use crate::channel::{ChannelSchema, RSISInterface, SampleReader, SampleWriter};
+use crate::recorder::{element_size, from_bytes, to_bytes};
+
+// Largest datagram a bridge receives
+const MAX_FRAME : usize = 65536;
+
+#[derive(Copy,Clone,PartialEq,Debug,Serialize,Deserialize)]
+pub enum BridgeTransport {
+    UNIX, // datagram socket bound to a filesystem path
+    UDP,  // host:port addresses
+}
+
+#[derive(Copy,Clone,PartialEq,Debug,Serialize,Deserialize)]
+pub enum BridgeDirection {
+    INBOUND,  // frames received on the socket are sent into the channel
+    OUTBOUND, // messages on the channel are sent to the peer
+}
+
+// Describes one bridge. Inbound bridges need an address to bind, outbound
+// bridges a peer to send to
+#[derive(Clone,Debug,Serialize,Deserialize)]
+pub struct BridgeConfig {
+    pub channel : String,
+    pub schema : ChannelSchema,
+    #[serde(default)]
+    pub sample : bool, // a sample channel, rather than a queue
+    pub transport : BridgeTransport,
+    pub direction : BridgeDirection,
+    #[serde(default)]
+    pub bind : String, // "" to leave an outbound socket unbound
+    #[serde(default)]
+    pub peer : String,
+}
+
+// Packed as MessagePack bin rather than an array of integers
+struct Bytes(Vec<u8>);
+
+impl Serialize for Bytes {
+    fn serialize<S : Serializer>(&self, serializer : S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Bytes;
+    fn expecting(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("bytes")
+    }
+    fn visit_bytes<E>(self, v : &[u8]) -> Result<Bytes, E> {
+        Ok(Bytes(v.to_vec()))
+    }
+    fn visit_byte_buf<E>(self, v : Vec<u8>) -> Result<Bytes, E> {
+        Ok(Bytes(v))
+    }
+    // tools without a bin type may send an array of integers
+    fn visit_seq<A : SeqAccess<'de>>(self, mut seq : A) -> Result<Bytes, A::Error> {
+        let mut bytes = Vec::new();
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(Bytes(bytes))
+    }
+}
+
+impl<'de> Deserialize<'de> for Bytes {
+    fn deserialize<D : Deserializer<'de>>(deserializer : D) -> Result<Bytes, D::Error> {
+        deserializer.deserialize_bytes(BytesVisitor)
+    }
+}
+
+// One message. tick and time are those of the sending frame, and are
+// informational only on receipt
+#[derive(Serialize, Deserialize)]
+struct Frame {
+    channel : String,
+    tick : i64,
+    time : f64,
+    element : String, // "u8", "f64", ..., see recorder::element_types
+    element_size : usize,
+    data : Bytes,
+}
+
+// Each with the peer to send to, resolved once when the socket is opened
+enum Socket {
+    #[cfg(unix)]
+    UNIX(UnixDatagram, Option<String>, Option<unix::SocketAddr>), // with the bound path, removed on drop
+    UDP(UdpSocket, Option<SocketAddr>),
+}
+
+#[cfg(unix)]
+fn bind_unix(path : &str) -> io::Result<UnixDatagram> {
+    // a socket left behind by an earlier run would make bind fail
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if meta.file_type().is_socket() {
+            std::fs::remove_file(path)?;
+        }
+    }
+    UnixDatagram::bind(path)
+}
+
+impl Socket {
+    // Sockets never block, so bridges only do work that is already waiting
+    fn open(config : &BridgeConfig) -> io::Result<Socket> {
+        let peer = config.peer.as_str();
+        let socket = match config.transport {
+            #[cfg(unix)]
+            BridgeTransport::UNIX => {
+                let peer = match peer.is_empty() {
+                    true => None,
+                    false => Some(unix::SocketAddr::from_pathname(peer)?),
+                };
+                match config.bind.is_empty() {
+                    true => Socket::UNIX(UnixDatagram::unbound()?, None, peer),
+                    false => Socket::UNIX(bind_unix(&config.bind)?, Some(config.bind.clone()), peer),
+                }
+            },
+            #[cfg(not(unix))]
+            BridgeTransport::UNIX => return Err(io::Error::new(io::ErrorKind::Unsupported, "UNIX sockets are not supported on this platform")),
+            BridgeTransport::UDP => {
+                let peer = match peer.is_empty() {
+                    true => None,
+                    false => match peer.to_socket_addrs()?.next() {
+                        Some(addr) => Some(addr),
+                        None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "bridge peer has no address")),
+                    },
+                };
+                Socket::UDP(UdpSocket::bind(if config.bind.is_empty() { "0.0.0.0:0" } else { &config.bind })?, peer)
+            },
+        };
+        match &socket {
+            #[cfg(unix)]
+            Socket::UNIX(socket, _, _) => socket.set_nonblocking(true)?,
+            Socket::UDP(socket, _) => socket.set_nonblocking(true)?,
+        }
+        Ok(socket)
+    }
+    fn send(&self, buf : &[u8]) -> io::Result<usize> {
+        let unset = || io::Error::new(io::ErrorKind::NotConnected, "bridge has no peer");
+        match self {
+            #[cfg(unix)]
+            Socket::UNIX(socket, _, peer) => socket.send_to_addr(buf, peer.as_ref().ok_or_else(unset)?),
+            Socket::UDP(socket, peer) => socket.send_to(buf, peer.ok_or_else(unset)?),
+        }
+    }
+    fn recv(&self, buf : &mut [u8]) -> io::Result<usize> {
+        match self {
+            #[cfg(unix)]
+            Socket::UNIX(socket, _, _) => socket.recv(buf),
+            Socket::UDP(socket, _) => socket.recv(buf),
+        }
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Socket::UNIX(_, Some(path), _) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// What one datagram received by an inbound bridge held
+enum Inbound {
+    MESSAGE(DataBuffer),
+    UNDECODED,  // not a frame, or a frame for another channel
+    MISMATCHED, // a frame whose data does not match the channel schema
+}
+
+// Whether a frame's data has the element type and size of the schema, and
+// as many elements as its shape if it has one. MessagePack schemas take
+// bytes
+fn matches_schema(frame : &Frame, schema : &ChannelSchema) -> bool {
+    match schema {
+        ChannelSchema::ARRAY { element, shape } => {
+            let len = frame.data.0.len();
+            frame.element == *element
+                && element_size(element) == Some(frame.element_size)
+                && len % frame.element_size == 0
+                && (shape.is_empty() || len == frame.element_size * shape.iter().product::<usize>())
+        },
+        ChannelSchema::MSGPACK(_) => frame.element == "u8" && frame.element_size == 1,
+    }
+}
+
+enum BridgeEnd {
+    TX(Box<dyn ChannelTx>),
+    RX(Box<dyn ChannelRx>),
+    WRITER(SampleWriter),
+    // with the sim tick of the last sample sent. A sample is only sent once
+    // per tick, whichever thread wrote it
+    READER(SampleReader, Option<i64>),
+}
+
+// Model that moves messages between one named channel and a socket, once
+// per frame. Frames that cannot be sent or decoded are counted and dropped,
+// as are frames that do not match the schema of the channel.
+// Bridges are implemented via the BaseModel trait interface, so they are
+// scheduled the same way as the models they talk to
+pub struct Bridge {
+    config : BridgeConfig,
+    socket : Socket,
+    end : Option<BridgeEnd>,
+    buffer : Vec<u8>,
+    dropped : u64,
+    rejected : u64,
+}
+
+impl Bridge {
+    pub fn open(config : BridgeConfig) -> io::Result<Bridge> {
+        let needed = match config.direction {
+            BridgeDirection::INBOUND => &config.bind,
+            BridgeDirection::OUTBOUND => &config.peer,
+        };
+        if needed.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "bridge has no address to bind or send to"));
+        }
+        Ok(Bridge {
+            socket : Socket::open(&config)?,
+            config : config,
+            end : None,
+            buffer : vec![0u8; MAX_FRAME],
+            dropped : 0,
+            rejected : 0,
+        })
+    }
+
+    // Frames lost to a full socket or that could not be decoded
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    // Frames received that did not match the channel schema
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    fn request_end(&self, interface : &mut Box<dyn Framework>) -> Option<BridgeEnd> {
+        let rsis = interface.as_any().downcast_ref::<RSISInterface>()?;
+        let (name, schema) = (&self.config.channel, &self.config.schema);
+        let result = match (self.config.direction, self.config.sample) {
+            (BridgeDirection::INBOUND, false) => rsis.request_named_tx(name, schema).map(BridgeEnd::TX),
+            (BridgeDirection::OUTBOUND, false) => rsis.request_named_rx(name, schema).map(BridgeEnd::RX),
+            (BridgeDirection::INBOUND, true) => rsis.request_sample_writer(name, schema).map(BridgeEnd::WRITER),
+            (BridgeDirection::OUTBOUND, true) => rsis.request_sample_reader(name, schema).map(|reader| BridgeEnd::READER(reader, None)),
+        };
+        match result {
+            Ok(end) => Some(end),
+            Err(err) => {
+                println!("Bridge could not open channel {}: {:?}", name, err);
+                None
+            }
+        }
+    }
+
+    fn send_frame(&mut self, interface : &Box<dyn Framework>, data : DataBuffer) {
+        let (element, element_size, bytes) = to_bytes(data);
+        let frame = Frame {
+            channel : self.config.channel.clone(),
+            tick : interface.get_simtick(),
+            time : interface.get_simtime(),
+            element : String::from(element),
+            element_size : element_size,
+            data : Bytes(bytes),
+        };
+        let sent = match rmps::to_vec_named(&frame) {
+            Ok(packed) => self.socket.send(&packed).is_ok(),
+            Err(_) => false,
+        };
+        if !sent {
+            self.dropped += 1;
+        }
+    }
+
+    // None once nothing more is waiting
+    fn recv_frame(&mut self) -> Option<Inbound> {
+        let len = match self.socket.recv(&mut self.buffer) {
+            Ok(len) => len,
+            Err(err) => {
+                if err.kind() != io::ErrorKind::WouldBlock {
+                    println!("Bridge for channel {} failed to receive: {}", self.config.channel, err);
+                }
+                return None;
+            }
+        };
+        let frame = match rmps::from_slice::<Frame>(&self.buffer[..len]) {
+            Ok(frame) if frame.channel == self.config.channel => frame,
+            _ => return Some(Inbound::UNDECODED)
+        };
+        if !matches_schema(&frame, &self.config.schema) {
+            return Some(Inbound::MISMATCHED);
+        }
+        Some(match from_bytes(&frame.element, frame.element_size, frame.data.0) {
+            Some(data) => Inbound::MESSAGE(data),
+            None => Inbound::UNDECODED
+        })
+    }
+    fn count(&mut self, result : Inbound) -> Option<DataBuffer> {
+        match result {
+            Inbound::MESSAGE(data) => return Some(data),
+            Inbound::UNDECODED => self.dropped += 1,
+            Inbound::MISMATCHED => self.rejected += 1,
+        }
+        None
+    }
+}
+
+impl BaseModel for Bridge {
+    fn config(&mut self) -> ConfigStatus {
+        ConfigStatus::OK
+    }
+    fn init(&mut self, interface : &mut Box<dyn Framework>) -> RuntimeStatus {
+        // frames that arrived between runs belong to neither
+        if self.config.direction == BridgeDirection::INBOUND {
+            while self.recv_frame().is_some() {}
+        }
+        self.end = self.request_end(interface);
+        match self.end {
+            Some(_) => RuntimeStatus::OK,
+            None => RuntimeStatus::ERROR
+        }
+    }
+    fn step(&mut self, interface : &mut Box<dyn Framework>) -> RuntimeStatus {
+        let mut end = match self.end.take() {
+            Some(end) => end,
+            None => return RuntimeStatus::ERROR
+        };
+        match &mut end {
+            BridgeEnd::RX(rx) => while let Ok(data) = rx.try_recv() {
+                self.send_frame(interface, data);
+            },
+            BridgeEnd::READER(reader, last) => if let Some(sample) = reader.read() {
+                if *last != Some(sample.tick) {
+                    *last = Some(sample.tick);
+                    self.send_frame(interface, sample.data);
+                }
+            },
+            BridgeEnd::TX(tx) => while let Some(result) = self.recv_frame() {
+                // a full subscriber queue is counted by the channel
+                if let Some(data) = self.count(result) {
+                    let _ = tx.send(data);
+                }
+            },
+            BridgeEnd::WRITER(writer) => while let Some(result) = self.recv_frame() {
+                if let Some(data) = self.count(result) {
+                    writer.write(data);
+                }
+            },
+        }
+        self.end = Some(end);
+        RuntimeStatus::OK
+    }
+    fn pause(&mut self) -> RuntimeStatus {
+        RuntimeStatus::OK
+    }
+    fn stop(&mut self) -> RuntimeStatus {
+        if self.dropped > 0 {
+            println!("Bridge for channel {} dropped {} frames", self.config.channel, self.dropped);
+        }
+        if self.rejected > 0 {
+            println!("Bridge for channel {} rejected {} frames not matching its schema", self.config.channel, self.rejected);
+        }
+        RuntimeStatus::OK
+    }
+    fn msg_get(&self, _id : BufferStruct, _cb : SizeCallback) -> u32 {
+        1
+    }
+    fn msg_set(&mut self, _id : BufferStruct, _data : BufferStruct) -> u32 {
+        1
+    }
+    fn get_ptr(&self, _id : BufferStruct) -> *const u8 {
+        0 as *const u8
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::epoch::FrameClock;
+    use std::sync::mpsc::TryRecvError;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    fn socket_path(name : &str) -> String {
+        let path : PathBuf = std::env::temp_dir().join(format!("rsis-bridge-{}-{}.sock", std::process::id(), name));
+        String::from(path.to_str().unwrap())
+    }
+
+    fn schema() -> ChannelSchema {
+        ChannelSchema::ARRAY { element : String::from("f64"), shape : vec![2] }
+    }
+
+    fn config(direction : BridgeDirection, bind : &str, peer : &str) -> BridgeConfig {
+        BridgeConfig {
+            channel : String::from("tlm"),
+            schema : schema(),
+            sample : false,
+            transport : BridgeTransport::UNIX,
+            direction : direction,
+            bind : String::from(bind),
+            peer : String::from(peer),
+        }
+    }
+
+    // Interface of a separate process, with its own channels
+    fn framework() -> Box<dyn Framework> {
+        Box::new(RSISInterface::new().for_thread(Arc::new(FrameClock::new(10.0, 10.0)), &[]))
+    }
+
+    fn rsis(framework : &Box<dyn Framework>) -> &RSISInterface {
+        framework.as_any().downcast_ref::<RSISInterface>().unwrap()
+    }
+
+    fn frame(element : &str, element_size : usize, data : Vec<u8>) -> Vec<u8> {
+        rmps::to_vec_named(&Frame {
+            channel : String::from("tlm"),
+            tick : 0,
+            time : 0.0,
+            element : String::from(element),
+            element_size : element_size,
+            data : Bytes(data),
+        }).unwrap()
+    }
+
+    fn values(data : &[f64]) -> Vec<u8> {
+        data.iter().flat_map(|val| val.to_ne_bytes()).collect()
+    }
+
+    #[test]
+    fn messages_cross_between_bridges() {
+        let path = socket_path("round-trip");
+        let mut sending = framework();
+        let mut receiving = framework();
+        let mut outbound = Bridge::open(config(BridgeDirection::OUTBOUND, "", &path)).unwrap();
+        let mut inbound = Bridge::open(config(BridgeDirection::INBOUND, &path, "")).unwrap();
+        let mut tx = rsis(&sending).request_named_tx("tlm", &schema()).unwrap();
+        let mut rx = rsis(&receiving).request_named_rx("tlm", &schema()).unwrap();
+        assert!(matches!(inbound.init(&mut receiving), RuntimeStatus::OK));
+        assert!(matches!(outbound.init(&mut sending), RuntimeStatus::OK));
+
+        tx.send(DataBuffer::from_vec(vec![1.5f64, -2.0])).unwrap();
+        assert!(matches!(outbound.step(&mut sending), RuntimeStatus::OK));
+        assert!(matches!(inbound.step(&mut receiving), RuntimeStatus::OK));
+        assert_eq!(rx.try_recv().unwrap().raw_data(), &values(&[1.5, -2.0])[..]);
+        assert_eq!((outbound.dropped(), inbound.dropped(), inbound.rejected()), (0, 0, 0));
+    }
+
+    #[test]
+    fn frames_carry_the_channel_and_element_type() {
+        let path = socket_path("framing");
+        let _ = std::fs::remove_file(&path);
+        let peer = UnixDatagram::bind(&path).unwrap();
+        let mut sending = framework();
+        let mut outbound = Bridge::open(config(BridgeDirection::OUTBOUND, "", &path)).unwrap();
+        let mut tx = rsis(&sending).request_named_tx("tlm", &schema()).unwrap();
+        assert!(matches!(outbound.init(&mut sending), RuntimeStatus::OK));
+
+        tx.send(DataBuffer::from_vec(vec![3.0f64, 4.0])).unwrap();
+        assert!(matches!(outbound.step(&mut sending), RuntimeStatus::OK));
+        let mut buf = vec![0u8; MAX_FRAME];
+        let len = peer.recv(&mut buf).unwrap();
+        let frame : Frame = rmps::from_slice(&buf[..len]).unwrap();
+        assert_eq!(frame.channel, "tlm");
+        assert_eq!((frame.element.as_str(), frame.element_size), ("f64", 8));
+        assert_eq!(frame.data.0, values(&[3.0, 4.0]));
+        drop(peer);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn frames_not_matching_the_schema_are_rejected() {
+        let path = socket_path("schema");
+        let mut receiving = framework();
+        let mut inbound = Bridge::open(config(BridgeDirection::INBOUND, &path, "")).unwrap();
+        let mut rx = rsis(&receiving).request_named_rx("tlm", &schema()).unwrap();
+        assert!(matches!(inbound.init(&mut receiving), RuntimeStatus::OK));
+
+        let peer = UnixDatagram::unbound().unwrap();
+        peer.send_to(&frame("f32", 4, vec![0u8; 8]), &path).unwrap();
+        peer.send_to(&frame("f64", 8, values(&[1.0, 2.0, 3.0])), &path).unwrap();
+        peer.send_to(b"not a frame", &path).unwrap();
+        peer.send_to(&frame("f64", 8, values(&[5.0, 6.0])), &path).unwrap();
+        assert!(matches!(inbound.step(&mut receiving), RuntimeStatus::OK));
+        assert_eq!(rx.try_recv().unwrap().raw_data(), &values(&[5.0, 6.0])[..]);
+        assert_eq!(rx.try_recv().err(), Some(TryRecvError::Empty));
+        assert_eq!((inbound.rejected(), inbound.dropped()), (2, 1));
+    }
+}
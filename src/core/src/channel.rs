@@ -3,105 +3,1030 @@ extern crate rsisappinterface;
 extern crate data_buffer;
 
 use data_buffer::DataBuffer;
+use serde::{Deserialize, Serialize};
 use std::any::Any;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex, mpsc, mpsc::Receiver, mpsc::Sender, mpsc::RecvError, mpsc::TryRecvError, mpsc::SendError};
+use std::cmp;
+use std::fmt;
+use std::io;
+use std::cell::UnsafeCell;
+use std::collections::{BinaryHeap, HashMap};
+use std::mem::MaybeUninit;
+use std::sync::{Arc, Condvar, Mutex, RwLock, mpsc::RecvError, mpsc::TryRecvError, mpsc::SendError};
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering, fence};
 
 use rsisappinterface::Framework;
 use rsisappinterface::ChannelRx;
 use rsisappinterface::ChannelTx;
 
-use crate::epoch::EpochTime;
+use crate::epoch::FrameClock;
+use crate::log::{LogBuffer, LogEntry, RateLimit, Severity};
+use crate::recorder::{Recorder, RecordedChannel};
+use crate::scheduler::ModelId;
+use crate::status;
 
-pub struct ChannelPairStorage {
-    tx : Sender<DataBuffer>,
-    rx : Option<Receiver<DataBuffer>>,
+// Payload carried by a named channel, registered by the first request for
+// the channel. Every later request must give the same schema
+#[derive(Clone,PartialEq,Debug,Serialize,Deserialize)]
+pub enum ChannelSchema {
+    ARRAY { element : String, shape : Vec<usize> }, // e.g. "f64" with shape [3]
+    MSGPACK(String), // name or definition of a MessagePack schema
 }
 
-impl ChannelPairStorage {
-    pub fn new() -> ChannelPairStorage {
-        let (_tx, _rx) = mpsc::channel();
-        ChannelPairStorage {
-            tx : _tx,
-            rx : Some(_rx),
+// Reasons a named channel could not be requested. The values are taken from
+// status::CHANNEL
+#[repr(u32)]
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum ChannelError {
+    BADSCHEMA  = status::CHANNEL.start,     // the channel was registered with a different schema
+    BADDEPTH   = status::CHANNEL.start + 1, // subscriber queues hold at least one message
+    BADKIND    = status::CHANNEL.start + 2, // the channel is a queue requested as a sample, or the reverse
+    BADLATENCY = status::CHANNEL.start + 3, // latencies are finite and not negative
+}
+
+// Messages a subscriber queue holds when no depth is requested
+pub const DEFAULT_DEPTH : usize = 64;
+
+// Channels requested through the Framework trait are numbered. Named
+// channels are requested directly from the RSISInterface
+#[derive(Clone,PartialEq,Eq,Hash,Debug,Serialize,Deserialize)]
+#[serde(untagged)]
+pub enum ChannelKey {
+    ID(i64),
+    NAME(String),
+}
+
+// What a full subscriber queue does with a new message
+#[derive(Copy,Clone,PartialEq,Debug,Serialize)]
+pub enum OverflowPolicy {
+    DROPOLDEST, // evict the oldest unread message
+    DROPNEWEST, // discard the new message
+    ERROR,      // discard the new message and fail the send
+}
+
+struct Slot<T> {
+    // twice the position the slot is ready for, plus one once it holds the
+    // message for that position. Doubling keeps a ring of one slot unambiguous
+    seq : AtomicUsize,
+    data : UnsafeCell<MaybeUninit<T>>,
+}
+
+// Bounded lock-free queue (Vyukov). Slots are allocated once, so neither end
+// allocates or blocks, and any thread may push or pop
+pub(crate) struct Ring<T> {
+    slots : Box<[Slot<T>]>,
+    head : AtomicUsize, // next read position
+    tail : AtomicUsize, // next write position
+}
+
+unsafe impl<T : Send> Send for Ring<T> {}
+unsafe impl<T : Send> Sync for Ring<T> {}
+
+impl<T> Ring<T> {
+    pub(crate) fn new(capacity : usize) -> Ring<T> {
+        Ring {
+            slots : (0..capacity).map(|i| Slot {
+                seq : AtomicUsize::new(2 * i),
+                data : UnsafeCell::new(MaybeUninit::uninit()),
+            }).collect(),
+            head : AtomicUsize::new(0),
+            tail : AtomicUsize::new(0),
+        }
+    }
+    // Gives the message back if the ring is full
+    pub(crate) fn push(&self, data : T) -> Result<(), T> {
+        let mut pos = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos % self.slots.len()];
+            let seq = slot.seq.load(Ordering::Acquire);
+            let diff = seq.wrapping_sub(pos.wrapping_mul(2)) as isize;
+            if diff == 0 {
+                match self.tail.compare_exchange_weak(pos, pos.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => {
+                        unsafe { (*slot.data.get()).write(data); }
+                        slot.seq.store(pos.wrapping_mul(2).wrapping_add(1), Ordering::Release);
+                        return Ok(());
+                    },
+                    Err(current) => pos = current
+                }
+            } else if diff < 0 {
+                return Err(data);
+            } else {
+                pos = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+    pub(crate) fn pop(&self) -> Option<T> {
+        let mut pos = self.head.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos % self.slots.len()];
+            let seq = slot.seq.load(Ordering::Acquire);
+            let diff = seq.wrapping_sub(pos.wrapping_mul(2).wrapping_add(1)) as isize;
+            if diff == 0 {
+                match self.head.compare_exchange_weak(pos, pos.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => {
+                        let data = unsafe { (*slot.data.get()).assume_init_read() };
+                        slot.seq.store(pos.wrapping_add(self.slots.len()).wrapping_mul(2), Ordering::Release);
+                        return Some(data);
+                    },
+                    Err(current) => pos = current
+                }
+            } else if diff < 0 {
+                return None;
+            } else {
+                pos = self.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+    fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        let head = self.head.load(Ordering::Acquire);
+        tail.wrapping_sub(head).min(self.slots.len())
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+// Slack when comparing sim times from threads running at different rates
+const TIME_EPSILON : f64 = 1e-9;
+
+struct Stamped {
+    data : DataBuffer,
+    sent : f64,    // sim time of the frame the message was sent in
+    visible : f64, // sim time the message may be received from
+    sender : Option<ModelId>,
+    origin : usize, // frame clock of the sending thread
+    seq : u64, // send order on the channel
+}
+
+// Ordered so that the message to be received first is the greatest, as
+// BinaryHeap takes the greatest first. Messages with the same visible time
+// are ordered by sender, and then by send order
+impl Ord for Stamped {
+    fn cmp(&self, other : &Self) -> cmp::Ordering {
+        other.visible.total_cmp(&self.visible)
+            .then(other.sender.cmp(&self.sender))
+            .then(other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Stamped {
+    fn partial_cmp(&self, other : &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Stamped {
+    fn eq(&self, other : &Self) -> bool {
+        self.cmp(other) == cmp::Ordering::Equal
+    }
+}
+
+impl Eq for Stamped {}
+
+// Messages waiting for one subscriber
+struct SubscriberQueue {
+    ring : Ring<Stamped>,
+    subscriber : Option<ModelId>, // the model that requested the receiver
+    overflow : OverflowPolicy,
+    dropped : AtomicU64,
+    pushed : AtomicUsize, // messages ever pushed, so recv can tell if any arrived
+    // only used to block in recv. Senders skip the lock when nobody waits
+    waiting : AtomicUsize,
+    lock : Mutex<()>,
+    available : Condvar,
+}
+
+impl SubscriberQueue {
+    // Gives the message back if it was rejected under OverflowPolicy::ERROR
+    fn push(&self, data : Stamped) -> Result<(), Stamped> {
+        let result = match self.overflow {
+            OverflowPolicy::DROPOLDEST => {
+                let mut data = data;
+                loop {
+                    match self.ring.push(data) {
+                        Ok(()) => break,
+                        Err(rejected) => {
+                            data = rejected;
+                            if self.ring.pop().is_some() {
+                                self.dropped.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    }
+                }
+                Ok(())
+            },
+            OverflowPolicy::DROPNEWEST => {
+                if self.ring.push(data).is_err() {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(())
+            },
+            OverflowPolicy::ERROR => self.ring.push(data).map_err(|rejected| {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                rejected
+            })
+        };
+        if result.is_ok() {
+            self.pushed.fetch_add(1, Ordering::SeqCst);
+            self.wake();
+        }
+        result
+    }
+    fn wake(&self) {
+        fence(Ordering::SeqCst);
+        if self.waiting.load(Ordering::SeqCst) > 0 {
+            let _guard = self.lock.lock().unwrap();
+            self.available.notify_all();
         }
     }
 }
 
-pub struct MpscRx {
-    rx : Receiver<DataBuffer>,
+// Every subscriber of a channel receives each message sent on it. The
+// subscriber list only changes when a receiver is requested or dropped.
+// A channel with a latency only delivers a message once the receiving thread
+// is that far past the frame it was sent in
+pub struct BroadcastStorage {
+    subscribers : RwLock<Vec<Arc<SubscriberQueue>>>,
+    senders : AtomicUsize, // open senders. Receivers stop waiting once there are none
+    dropped : AtomicU64, // from subscribers that have since been dropped
+    latency : AtomicU64, // f64 bits, in sim seconds
+    sequence : AtomicU64, // next send order
 }
 
-pub struct MpscTx {
-    tx : Sender<DataBuffer>,
+impl BroadcastStorage {
+    pub fn new(latency : f64) -> BroadcastStorage {
+        BroadcastStorage {
+            subscribers : RwLock::new(Vec::new()),
+            senders : AtomicUsize::new(0),
+            dropped : AtomicU64::new(0),
+            latency : AtomicU64::new(latency.to_bits()),
+            sequence : AtomicU64::new(0),
+        }
+    }
+    fn latency(&self) -> f64 {
+        f64::from_bits(self.latency.load(Ordering::Acquire))
+    }
+    fn set_latency(&self, latency : f64) {
+        self.latency.store(latency.to_bits(), Ordering::Release);
+    }
+    fn subscribe(self : &Arc<Self>, depth : usize, overflow : OverflowPolicy, subscriber : Option<ModelId>, time : Arc<FrameClock>) -> BroadcastRx {
+        let queue = Arc::new(SubscriberQueue {
+            ring : Ring::new(depth),
+            subscriber : subscriber,
+            overflow : overflow,
+            dropped : AtomicU64::new(0),
+            pushed : AtomicUsize::new(0),
+            waiting : AtomicUsize::new(0),
+            lock : Mutex::new(()),
+            available : Condvar::new(),
+        });
+        self.subscribers.write().unwrap().push(Arc::clone(&queue));
+        BroadcastRx {
+            storage : Arc::clone(self),
+            queue : queue,
+            time : time,
+            local : BinaryHeap::with_capacity(depth),
+            remote : BinaryHeap::with_capacity(depth),
+        }
+    }
+    fn sender(self : &Arc<Self>, tap : Tap) -> BroadcastTx {
+        self.senders.fetch_add(1, Ordering::AcqRel);
+        BroadcastTx {
+            storage : Arc::clone(self),
+            tap : tap,
+        }
+    }
+    // Messages lost by all subscribers of the channel
+    fn dropped(&self) -> u64 {
+        self.subscribers.read().unwrap().iter()
+            .fold(self.dropped.load(Ordering::Relaxed), |sum, queue| sum + queue.dropped.load(Ordering::Relaxed))
+    }
+    fn queues(&self) -> Vec<QueueInfo> {
+        self.subscribers.read().unwrap().iter().map(|queue| QueueInfo {
+            subscriber : queue.subscriber,
+            depth : queue.ring.slots.len(),
+            queued : queue.ring.len(),
+            overflow : queue.overflow,
+            dropped : queue.dropped.load(Ordering::Relaxed),
+        }).collect()
+    }
 }
 
-impl ChannelRx for MpscRx {
+// The newest value written to a sample channel, and the sim tick it was
+// written in. Ticks are counted at the base rate, so they order writes made
+// from different threads
+#[derive(Clone)]
+pub struct Sample {
+    pub data : DataBuffer,
+    pub tick : i64,
+}
+
+// Slots a sample channel cycles through. One holds the current value, and
+// the others are filled by writers or still being copied out by readers
+const SAMPLE_SLOTS : usize = 4;
+
+// No value has been written yet
+const NO_SAMPLE : u64 = u64::MAX;
+
+// Added to a slot's state for each reader copying its value out
+const SLOT_READER : usize = 2;
+// Set while a writer fills the slot, and while it holds the current value
+const SLOT_OWNED : usize = 1;
+
+struct SampleSlot {
+    value : UnsafeCell<Option<Sample>>,
+    tick : AtomicI64, // of the value, readable without copying it out
+    state : AtomicUsize,
+}
+
+// Latest-value storage. A writer claims a free slot, fills it, then swaps it
+// in as the current one, so readers never see a partial write and never
+// wait on a writer. Neither do writers wait on each other. A slot is only
+// reused once no reader is copying out of it
+pub struct SampleStorage {
+    slots : [SampleSlot; SAMPLE_SLOTS],
+    // index of the current slot in the low byte, and the number of swaps
+    // above it, so that a slot made current again is told apart
+    current : AtomicU64,
+    // open ends of the channel
+    writers : AtomicUsize,
+    readers : AtomicUsize,
+}
+
+unsafe impl Send for SampleStorage {}
+unsafe impl Sync for SampleStorage {}
+
+impl SampleStorage {
+    pub fn new() -> SampleStorage {
+        SampleStorage {
+            slots : [(); SAMPLE_SLOTS].map(|_| SampleSlot {
+                value : UnsafeCell::new(None),
+                tick : AtomicI64::new(0),
+                state : AtomicUsize::new(0),
+            }),
+            current : AtomicU64::new(NO_SAMPLE),
+            writers : AtomicUsize::new(0),
+            readers : AtomicUsize::new(0),
+        }
+    }
+    // Only retries while every other slot is being filled or read from
+    fn claim(&self) -> usize {
+        loop {
+            for (index, slot) in self.slots.iter().enumerate() {
+                if slot.state.compare_exchange(0, SLOT_OWNED, Ordering::SeqCst, Ordering::Relaxed).is_ok() {
+                    return index;
+                }
+            }
+            std::hint::spin_loop();
+        }
+    }
+    // A write from an earlier tick than the current value is dropped, so
+    // the value only moves forward when threads write concurrently
+    fn write(&self, sample : Sample) {
+        let index = self.claim();
+        let slot = &self.slots[index];
+        let tick = sample.tick;
+        slot.tick.store(tick, Ordering::SeqCst);
+        unsafe { *slot.value.get() = Some(sample); }
+        let mut current = self.current.load(Ordering::SeqCst);
+        loop {
+            let (swaps, previous) = match current {
+                NO_SAMPLE => (0, None),
+                _ => (current >> 8, Some((current & 0xff) as usize)),
+            };
+            if previous.is_some_and(|previous| self.slots[previous].tick.load(Ordering::SeqCst) > tick) {
+                slot.state.fetch_and(!SLOT_OWNED, Ordering::SeqCst);
+                return;
+            }
+            match self.current.compare_exchange(current, ((swaps + 1) << 8) | index as u64, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => {
+                    if let Some(previous) = previous {
+                        self.slots[previous].state.fetch_and(!SLOT_OWNED, Ordering::SeqCst);
+                    }
+                    return;
+                },
+                Err(now) => current = now,
+            }
+        }
+    }
+    fn read(&self) -> Option<Sample> {
+        loop {
+            let current = self.current.load(Ordering::SeqCst);
+            if current == NO_SAMPLE {
+                return None;
+            }
+            let slot = &self.slots[(current & 0xff) as usize];
+            slot.state.fetch_add(SLOT_READER, Ordering::SeqCst);
+            // the slot cannot be claimed while counted, so the value is whole
+            // if it was still current once counted
+            if self.current.load(Ordering::SeqCst) == current {
+                let sample = unsafe { (*slot.value.get()).clone() };
+                slot.state.fetch_sub(SLOT_READER, Ordering::SeqCst);
+                return sample;
+            }
+            slot.state.fetch_sub(SLOT_READER, Ordering::SeqCst);
+        }
+    }
+}
+
+// Writes to a sample channel, stamped with the sim tick of the thread that
+// requested the writer
+pub struct SampleWriter {
+    storage : Arc<SampleStorage>,
+    tap : Tap,
+}
+
+impl SampleWriter {
+    pub fn write(&self, data : DataBuffer) {
+        self.tap.record(&data);
+        self.storage.write(Sample { data : data, tick : self.tap.time.tick() });
+    }
+}
+
+pub struct SampleReader {
+    storage : Arc<SampleStorage>,
+}
+
+impl SampleReader {
+    // None until the first write
+    pub fn read(&self) -> Option<Sample> {
+        self.storage.read()
+    }
+}
+
+impl Drop for SampleWriter {
+    fn drop(&mut self) {
+        self.storage.writers.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl Drop for SampleReader {
+    fn drop(&mut self) {
+        self.storage.readers.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+// Passes messages sent through one end of a channel to the recorder, if one
+// is running
+struct Tap {
+    channel : Arc<RecordedChannel>,
+    sender : Option<ModelId>, // the model that requested this end
+    time : Arc<FrameClock>,
+    recorder : Arc<RwLock<Option<Recorder>>>,
+}
+
+impl Tap {
+    // Returns the sim time the message was sent at
+    fn record(&self, data : &DataBuffer) -> f64 {
+        let time = self.time.value();
+        if let Some(recorder) = &*self.recorder.read().unwrap() {
+            recorder.record(&self.channel, self.time.tick(), time, self.sender, data);
+        }
+        time
+    }
+}
+
+enum ChannelStorage {
+    QUEUE(Arc<BroadcastStorage>),
+    SAMPLE(Arc<SampleStorage>),
+}
+
+struct ChannelEntry {
+    schema : Option<ChannelSchema>, // not registered for numbered channels
+    storage : ChannelStorage,
+    // models that requested each end of the channel
+    publishers : Vec<ModelId>,
+    subscribers : Vec<ModelId>,
+}
+
+impl ChannelStorage {
+    // Number of open sending and receiving ends
+    fn ends(&self) -> (usize, usize) {
+        match self {
+            ChannelStorage::QUEUE(storage) => (storage.senders.load(Ordering::Acquire), storage.subscribers.read().unwrap().len()),
+            ChannelStorage::SAMPLE(storage) => (storage.writers.load(Ordering::Acquire), storage.readers.load(Ordering::Acquire)),
+        }
+    }
+}
+
+impl ChannelEntry {
+    fn new(schema : Option<ChannelSchema>, sample : bool, latency : f64) -> ChannelEntry {
+        ChannelEntry {
+            schema : schema,
+            storage : match sample {
+                true => ChannelStorage::SAMPLE(Arc::new(SampleStorage::new())),
+                false => ChannelStorage::QUEUE(Arc::new(BroadcastStorage::new(latency))),
+            },
+            publishers : Vec::new(),
+            subscribers : Vec::new(),
+        }
+    }
+    fn in_use(&self) -> bool {
+        !self.publishers.is_empty() || !self.subscribers.is_empty() || self.storage.ends() != (0, 0)
+    }
+}
+
+fn add_model(models : &mut Vec<ModelId>, caller : Option<ModelId>) {
+    if let Some(id) = caller {
+        if !models.contains(&id) {
+            models.push(id);
+        }
+    }
+}
+
+// Description of a channel, as listed to the frontend
+#[derive(Clone,Serialize)]
+pub struct ChannelInfo {
+    pub key : ChannelKey,
+    pub schema : Option<ChannelSchema>,
+    pub publishers : Vec<ModelId>,
+    pub subscribers : Vec<ModelId>,
+    pub kind : &'static str, // "queue" or "sample"
+    pub senders : usize, // open ends, including those of removed models
+    pub receivers : usize,
+    pub dropped : u64, // messages lost to full subscriber queues
+    pub latency : f64,
+    pub queues : Vec<QueueInfo>, // one per receiver of a queued channel
+}
+
+#[derive(Clone,Serialize)]
+pub struct QueueInfo {
+    pub subscriber : Option<ModelId>,
+    pub depth : usize,
+    pub queued : usize, // messages waiting to be received
+    pub overflow : OverflowPolicy,
+    pub dropped : u64,
+}
+
+pub struct BroadcastRx {
+    storage : Arc<BroadcastStorage>,
+    queue : Arc<SubscriberQueue>,
+    time : Arc<FrameClock>, // frame clock of the receiving thread
+    // messages taken from the queue that cannot be received yet, split by
+    // whether they were sent from this thread. Together they hold no more
+    // than the queue does, and never grow past that
+    local : BinaryHeap<Stamped>,
+    remote : BinaryHeap<Stamped>,
+}
+
+pub struct BroadcastTx {
+    storage : Arc<BroadcastStorage>,
+    tap : Tap,
+}
+
+impl BroadcastRx {
+    fn disconnected(&self) -> bool {
+        self.storage.senders.load(Ordering::SeqCst) == 0
+    }
+    // No message can arrive any more
+    fn closed(&self) -> bool {
+        self.disconnected() && self.queue.ring.len() == 0 && self.pending() == 0
+    }
+    fn pending(&self) -> usize {
+        self.local.len() + self.remote.len()
+    }
+    fn is_local(&self, msg : &Stamped) -> bool {
+        msg.origin == Arc::as_ptr(&self.time) as usize
+    }
+    // Moves messages from the queue into pending, applying the overflow
+    // policy of the queue once pending is full. Under OverflowPolicy::ERROR
+    // messages are left in the queue, so that sends fail once it fills
+    fn take_queued(&mut self) {
+        let depth = self.queue.ring.slots.len();
+        loop {
+            if self.pending() >= depth && self.queue.overflow == OverflowPolicy::ERROR {
+                return;
+            }
+            let msg = match self.queue.ring.pop() {
+                Some(msg) => msg,
+                None => return
+            };
+            if self.pending() >= depth {
+                self.queue.dropped.fetch_add(1, Ordering::Relaxed);
+                if self.queue.overflow != OverflowPolicy::DROPOLDEST {
+                    continue;
+                }
+                // the new message is kept unless it is the oldest
+                let oldest_local = match (self.local.peek(), self.remote.peek()) {
+                    (Some(local), Some(remote)) => local > remote,
+                    (local, _) => local.is_some(),
+                };
+                let oldest = if oldest_local { &mut self.local } else { &mut self.remote };
+                if !oldest.peek().is_some_and(|oldest| *oldest > msg) {
+                    continue;
+                }
+                oldest.pop();
+            }
+            match self.is_local(&msg) {
+                true => self.local.push(msg),
+                false => self.remote.push(msg),
+            }
+        }
+    }
+    // Earliest visible message on a channel with a latency. A message from
+    // another thread is only received once every thread is known to be past
+    // the frame it was sent in, as until then an earlier message could still
+    // be sent. That point only moves when the threads are in step, so this
+    // never waits on other threads, and messages are received in the same
+    // frames on every run. Messages sent from this thread only wait out the
+    // latency
+    fn next_visible(&mut self) -> Option<DataBuffer> {
+        self.take_queued();
+        let now = self.time.value();
+        let horizon = self.time.horizon();
+        let local = self.local.peek().filter(|msg| msg.visible <= now + TIME_EPSILON);
+        let remote = self.remote.peek().filter(|msg| msg.visible <= now + TIME_EPSILON && msg.sent < horizon - TIME_EPSILON);
+        let from_local = match (local, remote) {
+            (Some(local), Some(remote)) => local > remote,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => return None,
+        };
+        match from_local {
+            true => self.local.pop(),
+            false => self.remote.pop(),
+        }.map(|msg| msg.data)
+    }
+    fn delayed(&self) -> bool {
+        self.storage.latency() > 0.0 || self.pending() != 0
+    }
+    fn next(&mut self) -> Option<DataBuffer> {
+        match self.delayed() {
+            true => self.next_visible(),
+            false => self.queue.ring.pop().map(|msg| msg.data),
+        }
+    }
+}
+
+impl ChannelRx for BroadcastRx {
+    // Blocks until a message can be received. Fails once every sender is
+    // gone and no messages are left. On a channel with a latency a message
+    // only becomes visible in a later frame of this thread, which waiting
+    // would never reach, so there it does not block and fails when no
+    // message is visible. Models should use try_recv on such channels
     fn recv(&mut self) -> Result<DataBuffer, RecvError> {
-        self.rx.recv()
+        if self.delayed() {
+            return self.next().ok_or(RecvError);
+        }
+        loop {
+            let pushed = self.queue.pushed.load(Ordering::SeqCst);
+            if let Some(data) = self.next() {
+                return Ok(data);
+            }
+            if self.closed() {
+                return Err(RecvError);
+            }
+            // check again once senders can see this receiver waiting
+            let guard = self.queue.lock.lock().unwrap();
+            self.queue.waiting.fetch_add(1, Ordering::SeqCst);
+            if self.queue.pushed.load(Ordering::SeqCst) == pushed && !self.closed() {
+                drop(self.queue.available.wait(guard).unwrap());
+            }
+            self.queue.waiting.fetch_sub(1, Ordering::SeqCst);
+        }
     }
+    // Never blocks. Empty while messages are not visible yet
     fn try_recv(&mut self) -> Result<DataBuffer, TryRecvError> {
-        self.rx.try_recv()
+        match self.next() {
+            Some(data) => Ok(data),
+            None if self.closed() => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty)
+        }
     }
 }
 
-impl ChannelTx for MpscTx {
+impl Drop for BroadcastRx {
+    fn drop(&mut self) {
+        self.storage.subscribers.write().unwrap().retain(|queue| !Arc::ptr_eq(queue, &self.queue));
+        self.storage.dropped.fetch_add(self.queue.dropped.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+}
+
+impl ChannelTx for BroadcastTx {
+    // A message sent while a channel has no subscribers is discarded. Fails
+    // if any subscriber rejected the message, though the others still get it.
+    // Holds the read lock on the subscriber list, which is only written when
+    // a receiver is requested or dropped, and copies the message for every
+    // subscriber but the last
     fn send(&mut self, data : DataBuffer) -> Result<(), SendError<DataBuffer>> {
-        self.tx.send(data)
+        let sent = self.tap.record(&data);
+        let visible = sent + self.storage.latency();
+        let seq = self.storage.sequence.fetch_add(1, Ordering::Relaxed);
+        let stamp = |data| Stamped {
+            data : data,
+            sent : sent,
+            visible : visible,
+            sender : self.tap.sender,
+            origin : Arc::as_ptr(&self.tap.time) as usize,
+            seq : seq,
+        };
+        let subscribers = self.storage.subscribers.read().unwrap();
+        let mut rejected = None;
+        if let Some((last, others)) = subscribers.split_last() {
+            for queue in others {
+                if let Err(msg) = queue.push(stamp(data.clone())) {
+                    rejected = Some(msg.data);
+                }
+            }
+            if let Err(msg) = last.push(stamp(data)) {
+                rejected = Some(msg.data);
+            }
+        }
+        match rejected {
+            Some(data) => Err(SendError(data)),
+            None => Ok(())
+        }
+    }
+}
+
+impl Drop for BroadcastTx {
+    fn drop(&mut self) {
+        if self.storage.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // wake receivers blocked in recv
+            for queue in self.storage.subscribers.read().unwrap().iter() {
+                let _guard = queue.lock.lock().unwrap();
+                queue.available.notify_all();
+            }
+        }
     }
 }
 
 pub struct RSISInterface {
-    map : Arc<Mutex<HashMap<i64, ChannelPairStorage>>>,
-    pub time : Arc<Mutex<EpochTime>>,
+    map : Arc<Mutex<HashMap<ChannelKey, ChannelEntry>>>,
+    time : Arc<FrameClock>, // frame clock of the thread using the interface
+    // model currently being called by the worker thread. 0 if none
+    caller : AtomicU64,
+    recorder : Arc<RwLock<Option<Recorder>>>,
+    // set before or after the channel is first requested
+    latencies : Arc<Mutex<HashMap<ChannelKey, f64>>>,
+    pub logs : Arc<LogBuffer>,
+    // one per model run by the thread that owns the interface
+    limits : Arc<[RateLimit]>,
 }
 
 impl RSISInterface {
     pub fn new() -> RSISInterface {
         RSISInterface {
             map : Arc::new(Mutex::new(HashMap::new())),
-            time : Arc::new(Mutex::new(EpochTime::new())),
+            time : Arc::new(FrameClock::new(1.0, 1.0)),
+            caller : AtomicU64::new(0),
+            recorder : Arc::new(RwLock::new(None)),
+            latencies : Arc::new(Mutex::new(HashMap::new())),
+            logs : Arc::new(LogBuffer::new()),
+            limits : Arc::new([]),
+        }
+    }
+    // Shares the channels of this interface, but reads the time from the
+    // given clock and limits the logging of the given models. Used to give
+    // each worker thread its own frame time
+    pub fn for_thread(&self, time : Arc<FrameClock>, models : &[ModelId]) -> RSISInterface {
+        RSISInterface {
+            map : Arc::clone(&self.map),
+            time : time,
+            caller : AtomicU64::new(0),
+            recorder : Arc::clone(&self.recorder),
+            latencies : Arc::clone(&self.latencies),
+            logs : Arc::clone(&self.logs),
+            limits : models.iter().map(|id| RateLimit::new(*id)).collect(),
         }
     }
+    // Forgets every channel. Ends still held by models stay connected to
+    // each other, but not to channels requested afterwards
     pub fn clear(&mut self) {
         let mut data = self.map.lock().unwrap();
         (*data).clear();
     }
+    // Forgets a model removed from the scheduler, and any channel left with
+    // no models and no open ends
+    pub fn release_model(&self, id : ModelId) {
+        let mut data = self.map.lock().unwrap();
+        for entry in data.values_mut() {
+            entry.publishers.retain(|model| *model != id);
+            entry.subscribers.retain(|model| *model != id);
+        }
+        data.retain(|_, entry| entry.in_use());
+    }
+    // Set by the worker thread before calling into a model, so that channel
+    // requests can be attributed to it
+    pub fn set_caller(&self, id : Option<ModelId>) {
+        self.caller.store(id.unwrap_or(0), Ordering::Release);
+    }
+    pub fn get_caller(&self) -> Option<ModelId> {
+        match self.caller.load(Ordering::Acquire) {
+            0 => None,
+            id => Some(id)
+        }
+    }
+    // Finds or registers a channel, checking the schema of a named channel
+    fn entry<'a>(&self, data : &'a mut HashMap<ChannelKey, ChannelEntry>, key : ChannelKey, schema : Option<&ChannelSchema>, sample : bool) -> Result<&'a mut ChannelEntry, ChannelError> {
+        let latency = self.latencies.lock().unwrap().get(&key).copied().unwrap_or(0.0);
+        let entry = data.entry(key).or_insert_with(|| ChannelEntry::new(schema.cloned(), sample, latency));
+        if entry.schema.as_ref() != schema {
+            return Err(ChannelError::BADSCHEMA);
+        }
+        Ok(entry)
+    }
+    fn subscribe(&self, key : ChannelKey, schema : Option<&ChannelSchema>, depth : usize, overflow : OverflowPolicy) -> Result<BroadcastRx, ChannelError> {
+        if depth == 0 {
+            return Err(ChannelError::BADDEPTH);
+        }
+        let mut data = self.map.lock().unwrap();
+        let entry = self.entry(&mut data, key, schema, false)?;
+        let storage = match &entry.storage {
+            ChannelStorage::QUEUE(storage) => Arc::clone(storage),
+            ChannelStorage::SAMPLE(_) => return Err(ChannelError::BADKIND),
+        };
+        add_model(&mut entry.subscribers, self.get_caller());
+        Ok(storage.subscribe(depth, overflow, self.get_caller(), Arc::clone(&self.time)))
+    }
+    fn make_tx(&self, key : ChannelKey, schema : Option<&ChannelSchema>) -> Result<Box<dyn ChannelTx>, ChannelError> {
+        let mut data = self.map.lock().unwrap();
+        let entry = self.entry(&mut data, key.clone(), schema, false)?;
+        let storage = match &entry.storage {
+            ChannelStorage::QUEUE(storage) => Arc::clone(storage),
+            ChannelStorage::SAMPLE(_) => return Err(ChannelError::BADKIND),
+        };
+        add_model(&mut entry.publishers, self.get_caller());
+        Ok(Box::new(storage.sender(self.tap(key, entry, false))))
+    }
+    // Named channels are reached by downcasting the Framework passed to a
+    // model through `as_any`. Every receiver requested for a channel gets its
+    // own copy of each message
+    pub fn request_named_rx(&self, name : &str, schema : &ChannelSchema) -> Result<Box<dyn ChannelRx>, ChannelError> {
+        self.request_named_rx_queue(name, schema, DEFAULT_DEPTH, OverflowPolicy::DROPOLDEST)
+    }
+    // Same as `request_named_rx`, holding up to `depth` unread messages
+    pub fn request_named_rx_queue(&self, name : &str, schema : &ChannelSchema, depth : usize, overflow : OverflowPolicy) -> Result<Box<dyn ChannelRx>, ChannelError> {
+        let rx = self.subscribe(ChannelKey::NAME(String::from(name)), Some(schema), depth, overflow)?;
+        Ok(Box::new(rx))
+    }
+    pub fn request_named_tx(&self, name : &str, schema : &ChannelSchema) -> Result<Box<dyn ChannelTx>, ChannelError> {
+        self.make_tx(ChannelKey::NAME(String::from(name)), Some(schema))
+    }
+    fn sample(&self, name : &str, schema : &ChannelSchema, writer : bool) -> Result<(Arc<SampleStorage>, Tap), ChannelError> {
+        let key = ChannelKey::NAME(String::from(name));
+        let mut data = self.map.lock().unwrap();
+        let entry = self.entry(&mut data, key.clone(), Some(schema), true)?;
+        let storage = match &entry.storage {
+            ChannelStorage::SAMPLE(storage) => Arc::clone(storage),
+            ChannelStorage::QUEUE(_) => return Err(ChannelError::BADKIND),
+        };
+        add_model(if writer { &mut entry.publishers } else { &mut entry.subscribers }, self.get_caller());
+        match writer {
+            true => storage.writers.fetch_add(1, Ordering::AcqRel),
+            false => storage.readers.fetch_add(1, Ordering::AcqRel),
+        };
+        Ok((storage, self.tap(key, entry, true)))
+    }
+    // Sample channels hold only the newest value written to them. They share
+    // names with queued channels, so a name is either one or the other
+    pub fn request_sample_writer(&self, name : &str, schema : &ChannelSchema) -> Result<SampleWriter, ChannelError> {
+        let (storage, tap) = self.sample(name, schema, true)?;
+        Ok(SampleWriter {
+            storage : storage,
+            tap : tap,
+        })
+    }
+    pub fn request_sample_reader(&self, name : &str, schema : &ChannelSchema) -> Result<SampleReader, ChannelError> {
+        Ok(SampleReader {
+            storage : self.sample(name, schema, false)?.0,
+        })
+    }
+    fn tap(&self, key : ChannelKey, entry : &ChannelEntry, sample : bool) -> Tap {
+        Tap {
+            channel : Arc::new(RecordedChannel {
+                key : key,
+                schema : entry.schema.clone(),
+                sample : sample,
+            }),
+            sender : self.get_caller(),
+            time : Arc::clone(&self.time),
+            recorder : Arc::clone(&self.recorder),
+        }
+    }
+    // Messages sent on the channel in a frame at sim time t are received from
+    // the first frame at or after t + latency, once every thread has run past
+    // t. This makes delivery independent of thread timing. Threads only catch
+    // up with each other at the hyperperiod, or between steps if there is
+    // none, so a message may be received later than t + latency. Only
+    // applies to queued channels
+    pub fn set_latency(&self, key : ChannelKey, latency : f64) -> Result<(), ChannelError> {
+        if !(latency >= 0.0 && latency.is_finite()) {
+            return Err(ChannelError::BADLATENCY);
+        }
+        let data = self.map.lock().unwrap();
+        match data.get(&key).map(|entry| &entry.storage) {
+            Some(ChannelStorage::QUEUE(storage)) => storage.set_latency(latency),
+            Some(ChannelStorage::SAMPLE(_)) => return Err(ChannelError::BADKIND),
+            None => ()
+        }
+        self.latencies.lock().unwrap().insert(key, latency);
+        Ok(())
+    }
+    // Records every message sent on any channel to a log file, until
+    // recording is stopped. Replaces a recording already running
+    pub fn start_recording(&self, path : &str) -> io::Result<()> {
+        let recorder = Recorder::create(path)?;
+        let previous = self.recorder.write().unwrap().replace(recorder);
+        match previous {
+            Some(recorder) => recorder.finish(),
+            None => Ok(())
+        }
+    }
+    pub fn stop_recording(&self) -> io::Result<()> {
+        let previous = self.recorder.write().unwrap().take();
+        match previous {
+            Some(recorder) => recorder.finish(),
+            None => Ok(())
+        }
+    }
+    // Logs a message as the model being called. The Framework trait has no
+    // logging, so models reach this through as_any
+    pub fn log(&self, severity : Severity, message : &str) {
+        self.log_fmt(severity, format_args!("{}", message));
+    }
+    // Same as `log`, formatting the message straight into the buffer
+    pub fn log_fmt(&self, severity : Severity, message : fmt::Arguments) {
+        if !self.logs.enabled(severity) {
+            return;
+        }
+        let model = self.get_caller();
+        let time = self.time.value();
+        let suppressed = match model {
+            Some(id) => match RateLimit::admit(&self.limits, id, time, self.logs.rate()) {
+                Some(suppressed) => suppressed,
+                None => return
+            },
+            None => 0
+        };
+        self.logs.push(LogEntry::format(severity, model, self.time.tick(), time, message, suppressed));
+    }
+    pub fn debug(&self, message : &str) {
+        self.log(Severity::DEBUG, message);
+    }
+    pub fn info(&self, message : &str) {
+        self.log(Severity::INFO, message);
+    }
+    pub fn warn(&self, message : &str) {
+        self.log(Severity::WARN, message);
+    }
+    pub fn error(&self, message : &str) {
+        self.log(Severity::ERROR, message);
+    }
+    pub fn list_channels(&self) -> Vec<ChannelInfo> {
+        let data = self.map.lock().unwrap();
+        data.iter().map(|(key, entry)| ChannelInfo {
+            key : key.clone(),
+            schema : entry.schema.clone(),
+            publishers : entry.publishers.clone(),
+            subscribers : entry.subscribers.clone(),
+            kind : match &entry.storage {
+                ChannelStorage::QUEUE(_) => "queue",
+                ChannelStorage::SAMPLE(_) => "sample",
+            },
+            dropped : match &entry.storage {
+                ChannelStorage::QUEUE(storage) => storage.dropped(),
+                ChannelStorage::SAMPLE(_) => 0,
+            },
+            senders : entry.storage.ends().0,
+            receivers : entry.storage.ends().1,
+            latency : match &entry.storage {
+                ChannelStorage::QUEUE(storage) => storage.latency(),
+                ChannelStorage::SAMPLE(_) => 0.0,
+            },
+            queues : match &entry.storage {
+                ChannelStorage::QUEUE(storage) => storage.queues(),
+                ChannelStorage::SAMPLE(_) => Vec::new(),
+            },
+        }).collect()
+    }
 }
 
 impl Framework for RSISInterface {
     fn as_any(&self) -> &dyn Any {
         self
     }
+    // Ticks are counted at the rate of the fastest thread, so they compare
+    // across threads
     fn get_simtick(&self) -> i64 {
-        let data = self.time.lock().unwrap();
-        (*data).time
+        self.time.tick()
     }
     fn get_simtime(&self) -> f64 {
-        let data = self.time.lock().unwrap();
-        (*data).value()
+        self.time.value()
     }
     fn request_rx(&mut self, id : i64) -> Option<Box<dyn ChannelRx>> {
-        let mut data = self.map.lock().unwrap();
-        if !(*data).contains_key(&id) {
-            (*data).insert(id, ChannelPairStorage::new());
-        }
-        if (*data)[&id].rx.is_some() {
-            Some(Box::new(MpscRx {
-                rx : (*data).get_mut(&id).unwrap().rx.take().unwrap()
-            }))
-        } else {
-            None
+        match self.subscribe(ChannelKey::ID(id), None, DEFAULT_DEPTH, OverflowPolicy::DROPOLDEST) {
+            Ok(rx) => Some(Box::new(rx)),
+            Err(_) => None
         }
     }
     fn request_tx(&mut self, id : i64) -> Box<dyn ChannelTx> {
-        let mut data = self.map.lock().unwrap();
-        if !(*data).contains_key(&id) {
-            (*data).insert(id, ChannelPairStorage::new());
-        }
-        Box::new(MpscTx {
-            tx : (*data)[&id].tx.clone(),
-        })
+        // numbered channels have no schema, so this cannot fail
+        self.make_tx(ChannelKey::ID(id), None).unwrap()
     }
 }
 
@@ -110,6 +1035,153 @@ impl Clone for RSISInterface {
         RSISInterface {
             map : Arc::clone(&self.map),
             time : Arc::clone(&self.time),
+            caller : AtomicU64::new(self.caller.load(Ordering::Acquire)),
+            recorder : Arc::clone(&self.recorder),
+            latencies : Arc::clone(&self.latencies),
+            logs : Arc::clone(&self.logs),
+            limits : Arc::clone(&self.limits),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(value : u8) -> DataBuffer {
+        DataBuffer::from_vec(vec![value])
+    }
+
+    #[test]
+    fn delayed_messages_are_held_up_to_the_queue_depth() {
+        let schema = ChannelSchema::ARRAY { element : String::from("u8"), shape : vec![] };
+        let clock = Arc::new(FrameClock::new(10.0, 10.0));
+        let interface = RSISInterface::new().for_thread(Arc::clone(&clock), &[]);
+        interface.set_latency(ChannelKey::NAME(String::from("events")), 0.1).unwrap();
+        let mut rx = interface.request_named_rx_queue("events", &schema, 4, OverflowPolicy::DROPOLDEST).unwrap();
+        let mut tx = interface.request_named_tx("events", &schema).unwrap();
+        for value in 0..10 {
+            tx.send(message(value)).unwrap();
+            // not visible until the next frame, and never waits for it
+            assert_eq!(rx.try_recv().err(), Some(TryRecvError::Empty));
+        }
+        clock.advance();
+        clock.set_horizon(clock.value());
+        let received : Vec<u8> = (0..4).map(|_| rx.recv().unwrap().raw_data()[0]).collect();
+        assert_eq!(received, vec![6, 7, 8, 9]);
+        assert_eq!(interface.list_channels()[0].dropped, 6);
+        drop(tx);
+        assert_eq!(rx.try_recv().err(), Some(TryRecvError::Disconnected));
+        assert!(rx.recv().is_err());
+    }
+
+    #[test]
+    fn only_messages_from_other_threads_wait_for_the_horizon() {
+        let schema = ChannelSchema::ARRAY { element : String::from("u8"), shape : vec![] };
+        let clock = Arc::new(FrameClock::new(10.0, 10.0));
+        let other = Arc::new(FrameClock::new(10.0, 10.0));
+        let template = RSISInterface::new();
+        let interface = template.for_thread(Arc::clone(&clock), &[]);
+        let remote = template.for_thread(Arc::clone(&other), &[]);
+        interface.set_latency(ChannelKey::NAME(String::from("events")), 0.1).unwrap();
+        let mut rx = interface.request_named_rx_queue("events", &schema, 4, OverflowPolicy::DROPOLDEST).unwrap();
+        let mut local_tx = interface.request_named_tx("events", &schema).unwrap();
+        let mut remote_tx = remote.request_named_tx("events", &schema).unwrap();
+        remote_tx.send(message(1)).unwrap();
+        local_tx.send(message(2)).unwrap();
+        clock.advance();
+        other.advance();
+        // blocking recv would never reach a later frame, so it returns
+        assert_eq!(rx.recv().unwrap().raw_data()[0], 2);
+        assert!(rx.recv().is_err());
+        assert_eq!(rx.try_recv().err(), Some(TryRecvError::Empty));
+        clock.set_horizon(clock.value());
+        assert_eq!(rx.try_recv().unwrap().raw_data()[0], 1);
+    }
+
+    fn sample(tick : i64) -> Sample {
+        Sample { data : DataBuffer::from_vec(vec![tick]), tick : tick }
+    }
+
+    #[test]
+    fn samples_hold_the_latest_tick() {
+        let storage = SampleStorage::new();
+        assert!(storage.read().is_none());
+        storage.write(sample(2));
+        storage.write(sample(1)); // from a thread that is behind
+        assert_eq!(storage.read().unwrap().tick, 2);
+        storage.write(sample(2));
+        storage.write(sample(3));
+        assert_eq!(storage.read().unwrap().tick, 3);
+    }
+
+    #[test]
+    fn concurrent_samples_are_whole_and_move_forward() {
+        let storage = SampleStorage::new();
+        let writers = 3;
+        let writes = 2000;
+        std::thread::scope(|scope| {
+            for writer in 0..writers {
+                let storage = &storage;
+                scope.spawn(move || {
+                    for n in 0..writes {
+                        storage.write(sample(n * writers + writer));
+                    }
+                });
+            }
+            for _ in 0..3 {
+                scope.spawn(|| {
+                    let mut last = -1;
+                    for _ in 0..writes {
+                        if let Some(value) = storage.read() {
+                            // the data is always that of the tick it came with
+                            assert_eq!(value.data.raw_data(), &value.tick.to_ne_bytes()[..]);
+                            assert!(value.tick >= last);
+                            last = value.tick;
+                        }
+                    }
+                });
+            }
+        });
+        let latest = storage.read().unwrap();
+        assert_eq!(latest.tick, writes * writers - 1);
+        assert_eq!(latest.data.raw_data(), &latest.tick.to_ne_bytes()[..]);
+    }
+
+    #[test]
+    fn rings_wrap_around() {
+        for capacity in [1, 3] {
+            let ring = Ring::new(capacity);
+            for round in 0..10 {
+                for n in 0..capacity {
+                    assert!(ring.push(round * capacity + n).is_ok());
+                }
+                assert_eq!(ring.push(usize::MAX), Err(usize::MAX));
+                assert_eq!(ring.len(), capacity);
+                for n in 0..capacity {
+                    assert_eq!(ring.pop(), Some(round * capacity + n));
+                }
+                assert_eq!(ring.pop(), None);
+            }
         }
     }
+
+    // Sends 0..5 to a queue holding two messages. Returns what is received,
+    // which sends succeeded and the number of messages dropped
+    fn overflow(policy : OverflowPolicy) -> (Vec<u8>, Vec<bool>, u64) {
+        let schema = ChannelSchema::ARRAY { element : String::from("u8"), shape : vec![] };
+        let interface = RSISInterface::new();
+        let mut rx = interface.request_named_rx_queue("events", &schema, 2, policy).unwrap();
+        let mut tx = interface.request_named_tx("events", &schema).unwrap();
+        let sent = (0..5).map(|value| tx.send(message(value)).is_ok()).collect();
+        let received = std::iter::from_fn(|| rx.try_recv().ok()).map(|data| data.raw_data()[0]).collect();
+        (received, sent, interface.list_channels()[0].dropped)
+    }
+
+    #[test]
+    fn full_queues_apply_their_overflow_policy() {
+        assert_eq!(overflow(OverflowPolicy::DROPOLDEST), (vec![3, 4], vec![true; 5], 3));
+        assert_eq!(overflow(OverflowPolicy::DROPNEWEST), (vec![0, 1], vec![true; 5], 3));
+        assert_eq!(overflow(OverflowPolicy::ERROR), (vec![0, 1], vec![true, true, false, false, false], 3));
+    }
 }
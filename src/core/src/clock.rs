@@ -0,0 +1,275 @@
+// Clock sources used to pace frames. The scheduler only needs to read the
+// time and to sleep until a given time, which lets a simulation follow the
+// host clock, or be slaved to ticks from an external master
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{thread, time};
+
+#[cfg(unix)]
+use std::fs::File;
+#[cfg(unix)]
+use std::io::{Read, Write};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+pub trait ClockSource : Send + Sync {
+    // Seconds since an arbitrary epoch, fixed for the life of the clock
+    fn now(&self) -> f64;
+    // Blocks until `now` reaches the given time, or `stop` is set. A sleeper
+    // only notices `stop` straight away if `wake` is called after setting it
+    fn sleep_until(&self, time : f64, stop : &AtomicBool);
+    // Wakes every thread sleeping on the clock, so it checks its stop flag
+    fn wake(&self);
+}
+
+// Lets threads sleeping on a host clock be woken early
+struct Wakeup {
+    lock  : Mutex<()>,
+    woken : Condvar,
+}
+
+impl Wakeup {
+    fn new() -> Wakeup {
+        Wakeup {
+            lock  : Mutex::new(()),
+            woken : Condvar::new(),
+        }
+    }
+    fn sleep_until<F : Fn() -> f64>(&self, now : F, time : f64, stop : &AtomicBool) {
+        let mut guard = self.lock.lock().unwrap();
+        loop {
+            let remaining = time - now();
+            if stop.load(Ordering::Acquire) || remaining <= 0.0 {
+                return;
+            }
+            guard = self.woken.wait_timeout(guard, time::Duration::from_secs_f64(remaining)).unwrap().0;
+        }
+    }
+    fn wake(&self) {
+        let _guard = self.lock.lock().unwrap();
+        self.woken.notify_all();
+    }
+}
+
+// The host monotonic clock. This is the default
+pub struct MonotonicClock {
+    epoch : time::Instant,
+    wakeup : Wakeup,
+}
+
+impl MonotonicClock {
+    pub fn new() -> MonotonicClock {
+        MonotonicClock {
+            epoch : time::Instant::now(),
+            wakeup : Wakeup::new(),
+        }
+    }
+}
+
+impl ClockSource for MonotonicClock {
+    fn now(&self) -> f64 {
+        self.epoch.elapsed().as_secs_f64()
+    }
+    fn sleep_until(&self, time : f64, stop : &AtomicBool) {
+        self.wakeup.sleep_until(|| self.now(), time, stop);
+    }
+    fn wake(&self) {
+        self.wakeup.wake();
+    }
+}
+
+// International Atomic Time, for rigs that keep the host clock disciplined
+// to an external reference such as PTP
+#[cfg(target_os = "linux")]
+pub struct TaiClock {
+    wakeup : Wakeup,
+    // TAI when the clock was created, and the host clock at the same time
+    start : f64,
+    epoch : time::Instant,
+}
+
+#[cfg(target_os = "linux")]
+fn read_tai() -> std::io::Result<f64> {
+    let mut ts = libc::timespec { tv_sec : 0, tv_nsec : 0 };
+    if unsafe { libc::clock_gettime(libc::CLOCK_TAI, &mut ts) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(ts.tv_sec as f64 + ts.tv_nsec as f64 * 1e-9)
+}
+
+#[cfg(target_os = "linux")]
+impl TaiClock {
+    // Fails if the kernel does not provide CLOCK_TAI
+    pub fn new() -> std::io::Result<TaiClock> {
+        Ok(TaiClock {
+            wakeup : Wakeup::new(),
+            start : read_tai()?,
+            epoch : time::Instant::now(),
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl ClockSource for TaiClock {
+    // should a read fail, time carries on from the host monotonic clock
+    fn now(&self) -> f64 {
+        match read_tai() {
+            Ok(time) => time,
+            Err(_) => self.start + self.epoch.elapsed().as_secs_f64(),
+        }
+    }
+    // the wait is timed by the host clock, and repeated until TAI catches up
+    fn sleep_until(&self, time : f64, stop : &AtomicBool) {
+        self.wakeup.sleep_until(|| self.now(), time, stop);
+    }
+    fn wake(&self) {
+        self.wakeup.wake();
+    }
+}
+
+#[cfg(unix)]
+struct TickCount {
+    ticks  : u64,
+    closed : bool,
+}
+
+// Time driven by an external master. The source delivers native endian u64
+// tick counts, the same format read from a timerfd or eventfd, and every
+// tick advances the clock by a fixed period. Once the source closes, the
+// clock stops waiting and the simulation runs free
+#[cfg(unix)]
+pub struct ExternalTick {
+    period : f64,
+    count  : Arc<(Mutex<TickCount>, Condvar)>,
+    stop   : UnixStream, // written on drop to stop the reader
+    reader : Option<thread::JoinHandle<()>>,
+}
+
+// Reads tick counts until the source closes or `stop` becomes readable. The
+// source is closed when the reader returns
+#[cfg(unix)]
+fn read_ticks(mut source : File, stop : UnixStream, count : Arc<(Mutex<TickCount>, Condvar)>) {
+    let (lock, changed) = &*count;
+    let mut buf = [0u8; 8];
+    let mut filled = 0;
+    loop {
+        let mut fds = [
+            libc::pollfd { fd : source.as_raw_fd(), events : libc::POLLIN, revents : 0 },
+            libc::pollfd { fd : stop.as_raw_fd(), events : libc::POLLIN, revents : 0 },
+        ];
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+            if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            break;
+        }
+        if fds[1].revents != 0 {
+            return; // dropped, so nobody is waiting on the clock
+        }
+        // a count may arrive in pieces, so partial reads are kept
+        match source.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+            Err(_) => break
+        }
+        if filled == buf.len() {
+            filled = 0;
+            let mut data = lock.lock().unwrap();
+            data.ticks += u64::from_ne_bytes(buf);
+            changed.notify_all();
+        }
+    }
+    println!("External clock source closed");
+    lock.lock().unwrap().closed = true;
+    changed.notify_all();
+}
+
+#[cfg(unix)]
+impl ExternalTick {
+    // Connects to a master listening on a UNIX socket
+    pub fn connect(path : &str, period : f64) -> std::io::Result<ExternalTick> {
+        let stream = UnixStream::connect(path)?;
+        ExternalTick::from_source(File::from(OwnedFd::from(stream)), period)
+    }
+
+    // Takes ownership of an open file descriptor, e.g. a pipe or timerfd
+    pub unsafe fn from_fd(fd : RawFd, period : f64) -> std::io::Result<ExternalTick> {
+        ExternalTick::from_source(File::from_raw_fd(fd), period)
+    }
+
+    fn from_source(source : File, period : f64) -> std::io::Result<ExternalTick> {
+        let (stop, stopped) = UnixStream::pair()?;
+        let count = Arc::new((Mutex::new(TickCount { ticks : 0, closed : false }), Condvar::new()));
+        let ccount = Arc::clone(&count);
+        let reader = thread::spawn(move|| read_ticks(source, stopped, ccount));
+        Ok(ExternalTick {
+            period : period,
+            count  : count,
+            stop   : stop,
+            reader : Some(reader),
+        })
+    }
+}
+
+#[cfg(unix)]
+impl ClockSource for ExternalTick {
+    fn now(&self) -> f64 {
+        self.count.0.lock().unwrap().ticks as f64 * self.period
+    }
+    fn sleep_until(&self, time : f64, stop : &AtomicBool) {
+        let (lock, changed) = &*self.count;
+        let mut data = lock.lock().unwrap();
+        while !data.closed && !stop.load(Ordering::Acquire) && (data.ticks as f64) * self.period < time - 1e-9 {
+            data = changed.wait(data).unwrap();
+        }
+    }
+    fn wake(&self) {
+        let _data = self.count.0.lock().unwrap();
+        self.count.1.notify_all();
+    }
+}
+
+#[cfg(unix)]
+impl Drop for ExternalTick {
+    fn drop(&mut self) {
+        let _ = (&self.stop).write_all(&[0]);
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::io::IntoRawFd;
+
+    #[test]
+    fn external_ticks_pace_the_clock() {
+        let (mut master, source) = UnixStream::pair().unwrap();
+        let clock = unsafe { ExternalTick::from_fd(source.into_raw_fd(), 0.1) }.unwrap();
+        let stop = AtomicBool::new(false);
+        // a tick count may arrive split across reads
+        master.write_all(&2u64.to_ne_bytes()[..3]).unwrap();
+        master.write_all(&2u64.to_ne_bytes()[3..]).unwrap();
+        clock.sleep_until(0.2, &stop);
+        assert!((clock.now() - 0.2).abs() < 1e-9);
+
+        // a stop request ends a sleep that no tick would
+        thread::scope(|scope| {
+            scope.spawn(|| clock.sleep_until(10.0, &stop));
+            thread::sleep(time::Duration::from_millis(20));
+            stop.store(true, Ordering::Release);
+            clock.wake();
+        });
+
+        // dropping the clock stops the reader and closes the source
+        drop(clock);
+        let mut buf = [0u8; 1];
+        assert_eq!(master.read(&mut buf).unwrap(), 0);
+    }
+}
@@ -0,0 +1,105 @@
+
+extern crate rmp_serde as rmps;
+
+// Scheduler configuration. Each scheduler registers its keys with a type,
+// default value, and the states in which the key may be changed. Values are
+// exchanged with the frontend as MessagePack
+
+use crate::scheduler::SchedulerState;
+use crate::status;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Copy,Clone,PartialEq,Debug,Serialize)]
+#[serde(untagged)]
+pub enum ConfigValue {
+    BOOL(bool),
+    INT(i64),
+    FLOAT(f64),
+}
+
+impl ConfigValue {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ConfigValue::BOOL(_) => "bool",
+            ConfigValue::INT(_) => "int",
+            ConfigValue::FLOAT(_) => "float",
+        }
+    }
+
+    // Decodes a value of the same type as this one
+    fn decode(&self, data : &[u8]) -> Option<ConfigValue> {
+        match self {
+            ConfigValue::BOOL(_) => rmps::decode::from_read(data).ok().map(ConfigValue::BOOL),
+            ConfigValue::INT(_) => rmps::decode::from_read(data).ok().map(ConfigValue::INT),
+            // integers are accepted, since the frontend may not pack whole numbers as floats
+            ConfigValue::FLOAT(_) => rmps::decode::from_read(data).ok().map(ConfigValue::FLOAT),
+        }
+    }
+}
+
+impl fmt::Display for ConfigValue {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigValue::BOOL(val) => write!(f, "{}", val),
+            ConfigValue::INT(val) => write!(f, "{}", val),
+            ConfigValue::FLOAT(val) => write!(f, "{}", val),
+        }
+    }
+}
+
+pub struct ConfigKey {
+    pub name    : &'static str,
+    pub doc     : &'static str,
+    pub default : ConfigValue, // also sets the type of the key
+    pub states  : &'static [SchedulerState],
+}
+
+impl ConfigKey {
+    pub fn find<'a>(keys : &'a [ConfigKey], name : &str) -> Result<&'a ConfigKey, ConfigError> {
+        keys.iter().find(|key| key.name == name).ok_or(ConfigError::BADKEY)
+    }
+
+    // Checks that the key can be set now, and decodes its value
+    pub fn parse(&self, state : SchedulerState, data : &[u8]) -> Result<ConfigValue, ConfigError> {
+        if !self.states.contains(&state) {
+            return Err(ConfigError::BADSTATE);
+        }
+        self.default.decode(data).ok_or(ConfigError::BADVALUE)
+    }
+}
+
+// Reasons a configuration key could not be read or set. The values are
+// taken from status::CONFIG
+#[repr(u32)]
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum ConfigError {
+    BADKEY   = status::CONFIG.start,
+    BADVALUE = status::CONFIG.start + 1,
+    BADSTATE = status::CONFIG.start + 2,
+}
+
+// Description of a key and its current value, as listed to the frontend
+#[derive(Serialize)]
+pub struct ConfigEntry {
+    pub name    : &'static str,
+    #[serde(rename = "type")]
+    pub typ     : &'static str,
+    pub doc     : &'static str,
+    pub default : ConfigValue,
+    pub value   : ConfigValue,
+    pub states  : Vec<String>,
+}
+
+impl ConfigEntry {
+    pub fn new(key : &ConfigKey, value : ConfigValue) -> ConfigEntry {
+        ConfigEntry {
+            name    : key.name,
+            typ     : key.default.type_name(),
+            doc     : key.doc,
+            default : key.default,
+            value   : value,
+            states  : key.states.iter().map(|state| format!("{:?}", state)).collect(),
+        }
+    }
+}
@@ -1,32 +1,53 @@
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
 
+// Tolerance used when converting frames to sim ticks
+const TICK_EPSILON : f64 = 1e-9;
 
-#[derive(Copy, Clone)]
-pub struct EpochTime {
-    pub epoch    : i64,
-    pub time     : i64,
-    pub delta    : f64,
-    pub rollover : i64,
+// Frame clock of one worker thread. Only the owning thread advances it, and
+// any thread may read it without locking. Sim ticks are counted at the rate
+// of the fastest thread, so ticks read from different threads compare
+pub struct FrameClock {
+    frames : AtomicI64, // frames run by the thread
+    period : f64,       // sim seconds per frame
+    ticks  : f64,       // sim ticks per frame
+    // sim seconds that every thread is known to have run to. Only moved at
+    // points where the threads are in step, so it does not depend on timing
+    horizon : AtomicU64, // f64 bits
 }
 
-impl EpochTime {
-    pub fn increment(&mut self, steps : i64) -> () {
-        self.time += steps;
-        if self.time >= self.rollover {
-            self.time -= self.rollover;
-            self.epoch += 1;
+impl FrameClock {
+    pub fn new(frequency : f64, base_frequency : f64) -> FrameClock {
+        FrameClock {
+            frames : AtomicI64::new(0),
+            period : 1.0 / frequency,
+            ticks  : base_frequency / frequency,
+            horizon : AtomicU64::new(0.0f64.to_bits()),
         }
     }
 
+    pub fn advance(&self) {
+        self.frames.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn frames(&self) -> i64 {
+        self.frames.load(Ordering::Acquire)
+    }
+
+    // Sim ticks at the start of the current frame
+    pub fn tick(&self) -> i64 {
+        (self.frames() as f64 * self.ticks + TICK_EPSILON).floor() as i64
+    }
+
+    // Sim seconds at the start of the current frame
     pub fn value(&self) -> f64 {
-        self.time as f64 * self.delta
+        self.frames() as f64 * self.period
     }
-    
-    pub fn new() -> EpochTime {
-        EpochTime {
-            epoch : 0,
-            time  : 0,
-            delta : 1.0,
-            rollover : i64::MAX,
-        }
+
+    pub fn horizon(&self) -> f64 {
+        f64::from_bits(self.horizon.load(Ordering::Acquire))
     }
-}
\ No newline at end of file
+
+    pub fn set_horizon(&self, time : f64) {
+        self.horizon.store(time.to_bits(), Ordering::Release);
+    }
+}
@@ -0,0 +1,52 @@
+
+// Framework hooks are run by each worker thread at the start and end of
+// every frame, around the scheduled models. They exist for infrastructure
+// concerns such as telemetry capture, health checks, and time sync, which
+// are not part of the simulation itself and are not scheduled as models
+
+use rsisappinterface::RuntimeStatus;
+use rsisappinterface::Framework;
+use std::ffi::c_void;
+
+pub trait FrameHook {
+    fn frame_start(&mut self, _interface : &mut Box<dyn Framework>) -> RuntimeStatus {
+        RuntimeStatus::OK
+    }
+    fn frame_end(&mut self, _interface : &mut Box<dyn Framework>) -> RuntimeStatus {
+        RuntimeStatus::OK
+    }
+}
+
+// Called with the user object and the current sim tick. A non-zero return
+// value is treated as an error
+pub type FrameHookCallback = extern "C" fn(*mut c_void, i64) -> u32;
+
+// Hook registered through the C interface. Either callback may be omitted
+pub struct FrameHookExternal {
+    pub obj : *mut c_void,
+    pub start_fn : Option<FrameHookCallback>,
+    pub end_fn   : Option<FrameHookCallback>,
+}
+
+fn call_external(obj : *mut c_void, cb : Option<FrameHookCallback>, interface : &mut Box<dyn Framework>) -> RuntimeStatus {
+    match cb {
+        Some(f) => {
+            match f(obj, interface.get_simtick()) {
+                0 => RuntimeStatus::OK,
+                _ => RuntimeStatus::ERROR
+            }
+        },
+        None => RuntimeStatus::OK
+    }
+}
+
+impl FrameHook for FrameHookExternal {
+    fn frame_start(&mut self, interface : &mut Box<dyn Framework>) -> RuntimeStatus {
+        call_external(self.obj, self.start_fn, interface)
+    }
+    fn frame_end(&mut self, interface : &mut Box<dyn Framework>) -> RuntimeStatus {
+        call_external(self.obj, self.end_fn, interface)
+    }
+}
+
+unsafe impl Send for FrameHookExternal {}
@@ -8,9 +8,31 @@ mod scheduler;
 mod epoch;
 mod connection;
 mod channel;
+mod hooks;
+mod config;
+mod clock;
+mod watchdog;
+mod recorder;
+mod log;
+mod bridge;
+mod status;
+#[cfg(unix)]
+mod shm;
 
 pub use scheduler::Scheduler;
+pub use scheduler::SchedulerState;
 pub use rsis::NRTScheduler;
+pub use hooks::FrameHook;
+pub use channel::{RSISInterface, ChannelSchema, ChannelError, ChannelInfo, OverflowPolicy, QueueInfo};
+pub use channel::{ChannelKey, Sample, SampleReader, SampleWriter};
+pub use recorder::{Replay, RecordLog, RecordedChannel, RecordedMessage, read_log};
+pub use log::{Severity, LogEntry, LogRecord, LogBuffer};
+pub use bridge::{Bridge, BridgeConfig, BridgeDirection, BridgeTransport};
+pub use clock::{ClockSource, MonotonicClock};
+#[cfg(unix)]
+pub use clock::ExternalTick;
+#[cfg(unix)]
+pub use shm::{SignalExport, ExportedSignal};
 
 
 use rsisappinterface::BaseModel;
@@ -19,18 +41,38 @@ use rsisappinterface::ConfigStatusCallback;
 use rsisappinterface::RuntimeStatusCallback;
 use rsisappinterface::VoidCallback;
 use rsisappinterface::BufferStruct;
+use rsisappinterface::SizeCallback;
 use connection::Connection;
+use hooks::FrameHookCallback;
+use hooks::FrameHookExternal;
+use scheduler::ModelId;
+use scheduler::ScheduleError;
 
 pub use std::ffi::c_void;
 pub use libc::c_char;
+use std::ffi::{CStr, CString};
 
 static mut SCHEDULERS : Vec<Box<dyn Scheduler>> = vec![];
+static mut LAST_ERROR : u32 = 0; // reason the last schedule call failed
+static mut MESSAGE : Option<CString> = None; // text returned by `get_message`
 
+// General status codes. Errors specific to one area have their own enums,
+// with codes from the ranges in `status`
 #[repr(u32)]
 enum RSISStat {
-    OK,
-    BADARG,
-    ERR
+    OK      = status::GENERAL.start,
+    BADARG  = status::GENERAL.start + 1,
+    ERR     = status::GENERAL.start + 2,
+    TIMEOUT = status::TIMEOUT.start,
+}
+
+// Negative timeouts from the C interface mean wait forever
+fn timeout_from_secs(timeout : f64) -> Option<std::time::Duration> {
+    if timeout < 0.0 {
+        None
+    } else {
+        Some(std::time::Duration::from_secs_f64(timeout))
+    }
 }
 
 #[no_mangle]
@@ -43,7 +85,16 @@ pub extern "C" fn library_initialize() -> u32 {
 
 #[no_mangle]
 pub extern "C" fn library_shutdown() -> u32 {
-    return RSISStat::OK as u32;
+    let mut stat = RSISStat::OK;
+    unsafe {
+        for scheduler in SCHEDULERS.iter_mut() {
+            if scheduler.shutdown(timeout_from_secs(rsis::SHUTDOWN_TIMEOUT)) != 0 {
+                stat = RSISStat::TIMEOUT;
+            }
+        }
+        SCHEDULERS.clear();
+    }
+    return stat as u32;
 }
 
 #[no_mangle]
@@ -71,32 +122,99 @@ pub extern "C" fn new_thread(frequency : f64) -> u32 {
     return RSISStat::OK as u32;
 }
 
-#[no_mangle]
-pub extern "C" fn add_model(thread: i64, ptr: *mut c_void, divisor: i64, offset: i64) -> *mut c_void {
-    if ptr.is_null() {
-        return 0 as *mut c_void;
+// Writes the id of a newly scheduled model to the optional output argument,
+// and returns the stable pointer to the model. On failure the reason is
+// retrievable through `get_last_error`
+unsafe fn scheduled_model_ptr(id : Result<ModelId, ScheduleError>, id_out : *mut u64) -> *mut c_void {
+    match id {
+        Ok(val) => {
+            LAST_ERROR = RSISStat::OK as u32;
+            if !id_out.is_null() {
+                *id_out = val;
+            }
+            SCHEDULERS.get_mut(0).unwrap().get_model_ptr(val)
+        },
+        Err(err) => {
+            LAST_ERROR = err as u32;
+            0 as *mut c_void
+        }
     }
-    if thread < 0 {
-        return 0 as *mut c_void;
+}
+
+fn schedule_status(status : Result<(), ScheduleError>) -> u32 {
+    match status {
+        Ok(()) => RSISStat::OK as u32,
+        Err(err) => err as u32
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn get_last_error() -> u32 {
+    unsafe {
+        LAST_ERROR
     }
+}
+
+#[no_mangle]
+pub extern "C" fn add_model(thread: i64, ptr: *mut c_void, divisor: i64, offset: i64, id: *mut u64) -> *mut c_void {
     unsafe {
+        if ptr.is_null() {
+            return scheduled_model_ptr(Err(ScheduleError::NULLMODEL), id);
+        }
+        if thread < 0 {
+            return scheduled_model_ptr(Err(ScheduleError::BADTHREAD), id);
+        }
+        // the model is only taken over once it is known to fit, so that the
+        // caller still owns it if scheduling fails
+        if let Err(err) = SCHEDULERS.get_mut(0).unwrap().check_model_at(thread as usize, None, divisor, offset) {
+            return scheduled_model_ptr(Err(err), id);
+        }
         // notes for C++ programmers. Rust dyn traits are "fat", they're actually
         // implemented as two pointers. That's why the double box procedure must be
         // used to pass a dyn trait object through FFI
         let boxed_trait: Box<Box<dyn BaseModel + Send>> = Box::from_raw(ptr as *mut Box<dyn BaseModel + Send>);
-        return SCHEDULERS.get_mut(0).unwrap().add_model(boxed_trait, thread as usize, divisor, offset);
+        let newid = SCHEDULERS.get_mut(0).unwrap().add_model(boxed_trait, thread as usize, divisor, offset);
+        return scheduled_model_ptr(newid, id);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn insert_model_at(thread: i64, index: i64, ptr: *mut c_void, divisor: i64, offset: i64, id: *mut u64) -> *mut c_void {
+    unsafe {
+        if ptr.is_null() {
+            return scheduled_model_ptr(Err(ScheduleError::NULLMODEL), id);
+        }
+        if thread < 0 {
+            return scheduled_model_ptr(Err(ScheduleError::BADTHREAD), id);
+        }
+        if index < 0 {
+            return scheduled_model_ptr(Err(ScheduleError::BADINDEX), id);
+        }
+        if let Err(err) = SCHEDULERS.get_mut(0).unwrap().check_model_at(thread as usize, Some(index as usize), divisor, offset) {
+            return scheduled_model_ptr(Err(err), id);
+        }
+        let boxed_trait: Box<Box<dyn BaseModel + Send>> = Box::from_raw(ptr as *mut Box<dyn BaseModel + Send>);
+        let newid = SCHEDULERS.get_mut(0).unwrap().insert_model_at(boxed_trait, thread as usize, index as usize, divisor, offset);
+        return scheduled_model_ptr(newid, id);
     }
 }
 
 #[no_mangle]
 pub extern "C" fn add_model_by_callbacks(thread: i64,
-    objp: *mut c_void, configp: *mut c_void, initp:*mut c_void, stepp: *mut c_void, pausep: *mut c_void, stopp: *mut c_void, destp: *mut c_void, divisor: i64, offset: i64) -> *mut c_void
+    objp: *mut c_void, configp: *mut c_void, initp:*mut c_void, stepp: *mut c_void, pausep: *mut c_void, stopp: *mut c_void, destp: *mut c_void, divisor: i64, offset: i64, id: *mut u64) -> *mut c_void
 {
-    if objp.is_null() || configp.is_null() || initp.is_null() || stepp.is_null() || pausep.is_null() || stopp.is_null() || destp.is_null() {
-        return 0 as *mut c_void; // prevent seg fault later on
-    }
     // construct BaseModelExternal
     unsafe {
+        if objp.is_null() || configp.is_null() || initp.is_null() || stepp.is_null() || pausep.is_null() || stopp.is_null() || destp.is_null() {
+            return scheduled_model_ptr(Err(ScheduleError::NULLMODEL), id); // prevent seg fault later on
+        }
+        if thread < 0 {
+            return scheduled_model_ptr(Err(ScheduleError::BADTHREAD), id);
+        }
+        // the destructor must not run on an object the caller still owns
+        if let Err(err) = SCHEDULERS.get_mut(0).unwrap().check_model_at(thread as usize, None, divisor, offset) {
+            return scheduled_model_ptr(Err(err), id);
+        }
         let obj = BaseModelExternal {
             obj : objp,
             config_fn : std::mem::transmute::<*mut c_void, ConfigStatusCallback>(configp),
@@ -107,7 +225,8 @@ pub extern "C" fn add_model_by_callbacks(thread: i64,
             destructor_fn : std::mem::transmute::<*mut c_void, VoidCallback>(destp),
         };
         let boxed_trait : Box<Box<dyn BaseModel + Send>> = Box::new(Box::new(obj));
-        return SCHEDULERS.get_mut(0).unwrap().add_model(boxed_trait, thread as usize, divisor, offset);
+        let newid = SCHEDULERS.get_mut(0).unwrap().add_model(boxed_trait, thread as usize, divisor, offset);
+        return scheduled_model_ptr(newid, id);
     }
 }
 
@@ -116,6 +235,9 @@ pub extern "C" fn add_connection(src: *mut u8, dst: *mut u8, size: usize, thread
     if src.is_null() || dst.is_null() || size == 0 {
         return RSISStat::BADARG as u32;
     }
+    if thread < 0 {
+        return ScheduleError::BADTHREAD as u32;
+    }
     let obj = Connection {
         src : src as *mut i8,
         dst : dst as *mut i8,
@@ -123,12 +245,95 @@ pub extern "C" fn add_connection(src: *mut u8, dst: *mut u8, size: usize, thread
     };
     unsafe {
         let boxed_trait : Box<Box<dyn BaseModel + Send>> = Box::new(Box::new(obj));
-        let ptr = SCHEDULERS.get_mut(0).unwrap().add_model(boxed_trait, thread as usize, divisor, offset);
-        if ptr.is_null() {
-            return RSISStat::ERR as u32;
+        let status = SCHEDULERS.get_mut(0).unwrap().add_model(boxed_trait, thread as usize, divisor, offset);
+        return schedule_status(status.map(|_| ()));
+    }
+}
+
+// Adds a model that replays the messages recorded on a channel. A null name
+// replays numbered channel `id`
+#[no_mangle]
+pub extern "C" fn add_replay(path : *const c_char, name : *const c_char, id : i64, thread : i64, divisor : i64, offset : i64, model_id : *mut u64) -> u32 {
+    if path.is_null() {
+        return RSISStat::BADARG as u32;
+    }
+    if thread < 0 {
+        return ScheduleError::BADTHREAD as u32;
+    }
+    unsafe {
+        let key = match name.is_null() {
+            true => ChannelKey::ID(id),
+            false => match CStr::from_ptr(name).to_str() {
+                Ok(val) => ChannelKey::NAME(String::from(val)),
+                Err(_) => return RSISStat::BADARG as u32
+            }
+        };
+        let path_s = match CStr::from_ptr(path).to_str() {
+            Ok(val) => val,
+            Err(_) => return RSISStat::BADARG as u32
+        };
+        if let Err(err) = SCHEDULERS.get_mut(0).unwrap().check_model_at(thread as usize, None, divisor, offset) {
+            return err as u32;
+        }
+        let replay = match Replay::open(path_s, key) {
+            Ok(val) => val,
+            Err(err) => {
+                println!("Failed to load channel recording {}: {}", path_s, err);
+                return RSISStat::ERR as u32;
+            }
+        };
+        let boxed_trait : Box<Box<dyn BaseModel + Send>> = Box::new(Box::new(replay));
+        match SCHEDULERS.get_mut(0).unwrap().add_model(boxed_trait, thread as usize, divisor, offset) {
+            Ok(newid) => {
+                if !model_id.is_null() {
+                    *model_id = newid;
+                }
+                RSISStat::OK as u32
+            },
+            Err(err) => err as u32
+        }
+    }
+}
+
+// Schedules a bridge between a named channel and a socket. The bridge is
+// described by a MessagePack map matching BridgeConfig
+#[no_mangle]
+pub extern "C" fn add_bridge(config : BufferStruct, thread : i64, divisor : i64, offset : i64, model_id : *mut u64) -> u32 {
+    if thread < 0 {
+        return ScheduleError::BADTHREAD as u32;
+    }
+    unsafe {
+        let config_s = std::slice::from_raw_parts(config.ptr as *const u8, config.size as usize);
+        let config : BridgeConfig = match rmps::decode::from_read(config_s) {
+            Ok(val) => val,
+            Err(err) => {
+                println!("Invalid bridge description: {}", err);
+                return RSISStat::BADARG as u32;
+            }
+        };
+        // the socket is only bound once the bridge can be scheduled
+        if let Err(err) = SCHEDULERS.get_mut(0).unwrap().check_model_at(thread as usize, None, divisor, offset) {
+            return err as u32;
+        }
+        let channel = config.channel.clone();
+        let bridge = match Bridge::open(config) {
+            Ok(val) => val,
+            Err(err) => {
+                println!("Failed to open bridge for channel {}: {}", channel, err);
+                return RSISStat::ERR as u32;
+            }
+        };
+        let boxed_trait : Box<Box<dyn BaseModel + Send>> = Box::new(Box::new(bridge));
+        match SCHEDULERS.get_mut(0).unwrap().add_model(boxed_trait, thread as usize, divisor, offset) {
+            Ok(newid) => {
+                if !model_id.is_null() {
+                    *model_id = newid;
+                }
+                RSISStat::OK as u32
+            },
+            Err(err) => err as u32
         }
     }
-    return RSISStat::OK as u32;
 }
 
 #[no_mangle]
@@ -143,6 +348,220 @@ pub extern "C" fn remove_model(thread: usize, id: usize) -> u32 {
     }
 }
 
+#[no_mangle]
+pub extern "C" fn move_model(id: u64, thread: i64, index: i64) -> u32 {
+    if thread < 0 {
+        return ScheduleError::BADTHREAD as u32;
+    }
+    if index < 0 {
+        return ScheduleError::BADINDEX as u32;
+    }
+    unsafe {
+        schedule_status(SCHEDULERS.get_mut(0).unwrap().move_model(id, thread as usize, index as usize))
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn remove_model_by_id(id: u64) -> u32 {
+    unsafe {
+        schedule_status(SCHEDULERS.get_mut(0).unwrap().remove_model_by_id(id))
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn set_model_enabled(id: u64, enabled: u32) -> u32 {
+    unsafe {
+        schedule_status(SCHEDULERS.get_mut(0).unwrap().set_model_enabled(id, enabled != 0))
+    }
+}
+
+// Returns 1 if enabled, 0 if disabled, and -1 if the id is unknown. Reflects
+// the latest request, which takes effect at the next frame boundary
+#[no_mangle]
+pub extern "C" fn get_model_enabled(id: u64) -> i32 {
+    unsafe {
+        match SCHEDULERS.get_mut(0).unwrap().get_model_enabled(id) {
+            Some(true) => 1,
+            Some(false) => 0,
+            None => -1
+        }
+    }
+}
+
+// Changes the rate group and phase of a scheduled model. Only permitted
+// before the simulation runs, or while it is paused
+#[no_mangle]
+pub extern "C" fn set_model_rate(id: u64, divisor: i64, offset: i64) -> u32 {
+    unsafe {
+        schedule_status(SCHEDULERS.get_mut(0).unwrap().set_model_rate(id, divisor, offset))
+    }
+}
+
+// Registers C callbacks to run at the start and end of every frame. Either
+// callback may be null. A negative thread registers the hook on all threads
+#[no_mangle]
+pub extern "C" fn add_frame_hook(thread: i64, objp: *mut c_void, startp: *mut c_void, endp: *mut c_void) -> u32 {
+    if startp.is_null() && endp.is_null() {
+        return RSISStat::BADARG as u32;
+    }
+    unsafe {
+        let scheduler = SCHEDULERS.get_mut(0).unwrap();
+        let threads = if thread < 0 {
+            0..scheduler.get_num_threads() as usize
+        } else {
+            thread as usize..thread as usize + 1
+        };
+        for id in threads {
+            let hook = FrameHookExternal {
+                obj : objp,
+                start_fn : std::mem::transmute::<*mut c_void, Option<FrameHookCallback>>(startp),
+                end_fn   : std::mem::transmute::<*mut c_void, Option<FrameHookCallback>>(endp),
+            };
+            let status = scheduler.add_frame_hook(id, Box::new(hook));
+            if status.is_err() {
+                return schedule_status(status);
+            }
+        }
+    }
+    return RSISStat::OK as u32;
+}
+
+// Logs a message as the model being called, for models that cannot reach
+// the RSISInterface directly. Only works from inside model and hook calls
+#[no_mangle]
+pub extern "C" fn log_message(severity : u32, message : *const c_char) -> u32 {
+    let severity = match Severity::from_u32(severity) {
+        Some(val) => val,
+        None => return RSISStat::BADARG as u32
+    };
+    if message.is_null() {
+        return RSISStat::BADARG as u32;
+    }
+    let text = unsafe { CStr::from_ptr(message) }.to_string_lossy();
+    match log::log_from_thread(severity, &text) {
+        true => RSISStat::OK as u32,
+        false => RSISStat::ERR as u32
+    }
+}
+
+// Publishes signals to a POSIX shared memory segment, updated at the end of
+// every frame of a thread. The signals are described by a MessagePack array
+// of maps matching ExportedSignal. Not supported on other platforms, where
+// this always fails
+#[no_mangle]
+pub extern "C" fn add_signal_export(segment : *const c_char, thread : i64, signals : BufferStruct) -> u32 {
+    if segment.is_null() {
+        return RSISStat::BADARG as u32;
+    }
+    if thread < 0 {
+        return ScheduleError::BADTHREAD as u32;
+    }
+    #[cfg(unix)]
+    unsafe {
+        let segment_s = match CStr::from_ptr(segment).to_str() {
+            Ok(val) => val,
+            Err(_) => return RSISStat::BADARG as u32
+        };
+        let signals_s = std::slice::from_raw_parts(signals.ptr as *const u8, signals.size as usize);
+        let signals : Vec<ExportedSignal> = match rmps::decode::from_read(signals_s) {
+            Ok(val) => val,
+            Err(err) => {
+                println!("Invalid signal export description: {}", err);
+                return RSISStat::BADARG as u32;
+            }
+        };
+        // a frame hook can be added wherever a model can. Checked before the
+        // segment is created
+        if let Err(err) = SCHEDULERS.get_mut(0).unwrap().check_model_at(thread as usize, None, 1, 0) {
+            return err as u32;
+        }
+        let export = match SignalExport::create(segment_s, &signals) {
+            Ok(val) => val,
+            Err(err) => {
+                println!("Failed to create shared memory segment {}: {}", segment_s, err);
+                return RSISStat::ERR as u32;
+            }
+        };
+        return schedule_status(SCHEDULERS.get_mut(0).unwrap().add_frame_hook(thread as usize, Box::new(export)));
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = signals;
+        return RSISStat::ERR as u32;
+    }
+}
+
+// Paces frames with the host monotonic clock. This is the default
+#[no_mangle]
+pub extern "C" fn set_clock_monotonic() -> u32 {
+    unsafe {
+        schedule_status(SCHEDULERS.get_mut(0).unwrap().set_clock(Box::new(MonotonicClock::new())))
+    }
+}
+
+// Paces frames with CLOCK_TAI. Only available on Linux
+#[no_mangle]
+pub extern "C" fn set_clock_tai() -> u32 {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        match clock::TaiClock::new() {
+            Ok(tai) => return schedule_status(SCHEDULERS.get_mut(0).unwrap().set_clock(Box::new(tai))),
+            Err(err) => {
+                println!("Failed to read CLOCK_TAI: {}", err);
+                return RSISStat::ERR as u32;
+            }
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    return RSISStat::ERR as u32;
+}
+
+// Slaves frame pacing to ticks sent by a master listening on a UNIX socket.
+// Every tick advances the clock by `period` seconds
+#[no_mangle]
+pub extern "C" fn set_clock_socket(path : *const c_char, period : f64) -> u32 {
+    if path.is_null() || !(period > 0.0) {
+        return RSISStat::BADARG as u32;
+    }
+    #[cfg(unix)]
+    unsafe {
+        let path_s = match CStr::from_ptr(path).to_str() {
+            Ok(val) => val,
+            Err(_) => return RSISStat::BADARG as u32
+        };
+        match clock::ExternalTick::connect(path_s, period) {
+            Ok(tick) => return schedule_status(SCHEDULERS.get_mut(0).unwrap().set_clock(Box::new(tick))),
+            Err(err) => {
+                println!("Failed to connect to clock source {}: {}", path_s, err);
+                return RSISStat::ERR as u32;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    return RSISStat::ERR as u32;
+}
+
+// Same as `set_clock_socket`, reading ticks from an open file descriptor.
+// The library takes ownership of the descriptor
+#[no_mangle]
+pub extern "C" fn set_clock_fd(fd : i32, period : f64) -> u32 {
+    if fd < 0 || !(period > 0.0) {
+        return RSISStat::BADARG as u32;
+    }
+    #[cfg(unix)]
+    unsafe {
+        match clock::ExternalTick::from_fd(fd, period) {
+            Ok(tick) => return schedule_status(SCHEDULERS.get_mut(0).unwrap().set_clock(Box::new(tick))),
+            Err(err) => {
+                println!("Failed to read clock source {}: {}", fd, err);
+                return RSISStat::ERR as u32;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    return RSISStat::ERR as u32;
+}
+
 #[no_mangle]
 pub extern "C" fn init_scheduler() -> u32 {
     unsafe {
@@ -163,6 +582,19 @@ pub extern "C" fn step_scheduler(steps: u64) -> u32 {
     return RSISStat::OK as u32;
 }
 
+// Steps the scheduler and returns once the step has completed, or the
+// scheduler has ended or errored
+#[no_mangle]
+pub extern "C" fn step_scheduler_blocking(steps: u64, timeout: f64) -> u32 {
+    unsafe {
+        match SCHEDULERS.get_mut(0).unwrap().step_blocking(steps, timeout_from_secs(timeout)) {
+            Some(SchedulerState::PAUSED) => RSISStat::OK as u32,
+            Some(_) => RSISStat::ERR as u32,
+            None => RSISStat::TIMEOUT as u32
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn pause_scheduler() -> u32 {
     unsafe {
@@ -186,6 +618,31 @@ pub extern "C" fn end_scheduler() -> u32 {
     return RSISStat::OK as u32;
 }
 
+// Ends the simulation, stopping every model, and waits for all threads to
+// exit. Returns TIMEOUT if any thread did not exit in time, or panicked
+#[no_mangle]
+pub extern "C" fn shutdown_scheduler(timeout: f64) -> u32 {
+    unsafe {
+        if SCHEDULERS.get_mut(0).unwrap().shutdown(timeout_from_secs(timeout)) != 0 {
+            return RSISStat::TIMEOUT as u32;
+        }
+    }
+    return RSISStat::OK as u32;
+}
+
+// Ends the simulation if it is still running, joins all threads, and returns
+// the scheduler and its models to the CONFIG state so that it can be
+// initialized again
+#[no_mangle]
+pub extern "C" fn reset_scheduler() -> u32 {
+    unsafe {
+        if SCHEDULERS.get_mut(0).unwrap().reset() != 0 {
+            return RSISStat::ERR as u32;
+        }
+    }
+    return RSISStat::OK as u32;
+}
+
 #[no_mangle]
 pub extern "C" fn get_thread_number() -> i32 {
     unsafe {
@@ -200,26 +657,207 @@ pub extern "C" fn get_scheduler_state() -> i32 {
     }
 }
 
+// Blocks until the scheduler reaches the given state. Returns early with an
+// error if the scheduler ends or errors instead
+#[no_mangle]
+pub extern "C" fn wait_for_state(state: i32, timeout: f64) -> u32 {
+    let target = match SchedulerState::from_i32(state) {
+        Some(val) => val,
+        None => return RSISStat::BADARG as u32
+    };
+    unsafe {
+        match SCHEDULERS.get_mut(0).unwrap().wait_for_state(target, timeout_from_secs(timeout)) {
+            Some(reached) if reached == target => RSISStat::OK as u32,
+            Some(_) => RSISStat::ERR as u32,
+            None => RSISStat::TIMEOUT as u32
+        }
+    }
+}
+
+// Number of model panics caught since the scheduler was last reset
+#[no_mangle]
+pub extern "C" fn get_fault_count() -> u32 {
+    unsafe {
+        SCHEDULERS.get_mut(0).unwrap().get_faults().len() as u32
+    }
+}
+
+// Loads a description of a caught model panic, retrievable through
+// `get_message`
+#[no_mangle]
+pub extern "C" fn get_fault(index : u32) -> u32 {
+    unsafe {
+        match SCHEDULERS.get_mut(0).unwrap().get_faults().get(index as usize) {
+            Some(fault) => {
+                set_message(&fault.to_string());
+                RSISStat::OK as u32
+            },
+            None => RSISStat::BADARG as u32
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn get_scheduler_name() -> u32 {
+    unsafe {
+        set_message("NRTScheduler");
+    }
     return RSISStat::OK as u32;
 }
 
+// Returns the text loaded by the last call that produces a message. The
+// pointer is valid until the next such call
 #[no_mangle]
-pub extern "C" fn get_message() -> u32 {
-    return RSISStat::OK as u32;
+pub extern "C" fn get_message() -> *const c_char {
+    unsafe {
+        match &MESSAGE {
+            Some(msg) => msg.as_ptr(),
+            None => b"\0".as_ptr() as *const c_char
+        }
+    }
+}
+
+unsafe fn set_message(text : &str) {
+    // interior nul bytes cannot be passed through a C string
+    MESSAGE = CString::new(text.replace('\0', "")).ok();
 }
 
 // Utility methods, not related to running the scheduler and framework
 
+// Configuration keys are passed in as MessagePack strings
+unsafe fn config_key(key : &BufferStruct) -> Option<String> {
+    let key_s = std::slice::from_raw_parts(key.ptr as *const u8, key.size as usize);
+    rmps::decode::from_read(key_s).ok()
+}
+
+// Hands MessagePack data to the caller through a buffer it allocates
+unsafe fn copy_out(packed : &[u8], cb : SizeCallback) {
+    let ptr = (cb)(packed.len());
+    let slice = std::slice::from_raw_parts_mut(ptr, packed.len());
+    slice.clone_from_slice(packed);
+}
+
 #[no_mangle]
 pub extern "C" fn config_scheduler(key : BufferStruct, value : BufferStruct) -> u32 {
     unsafe {
-        let key_s = std::slice::from_raw_parts(key.ptr as *const u8, key.size as usize);
+        let key_s = match config_key(&key) {
+            Some(val) => val,
+            None => return RSISStat::BADARG as u32
+        };
         let val_s = std::slice::from_raw_parts(value.ptr as *const u8, value.size as usize);
-        match SCHEDULERS.get_mut(0).unwrap().config(key_s, val_s) {
-            None => { return RSISStat::OK as u32; },
-            Some(val) => { return val as u32; }
+        match SCHEDULERS.get_mut(0).unwrap().config(&key_s, val_s) {
+            Ok(_) => RSISStat::OK as u32,
+            Err(err) => {
+                println!("Failed to set scheduler config {}: {:?}", key_s, err);
+                err as u32
+            }
+        }
+    }
+}
+
+// Returns the current value of a configuration key, packed as MessagePack
+#[no_mangle]
+pub extern "C" fn get_scheduler_config(key : BufferStruct, cb : SizeCallback) -> u32 {
+    unsafe {
+        let key_s = match config_key(&key) {
+            Some(val) => val,
+            None => return RSISStat::BADARG as u32
+        };
+        match SCHEDULERS.get_mut(0).unwrap().get_config(&key_s) {
+            Ok(val) => match rmps::to_vec(&val) {
+                Ok(packed) => {
+                    copy_out(&packed, cb);
+                    RSISStat::OK as u32
+                },
+                Err(_) => RSISStat::ERR as u32
+            },
+            Err(err) => err as u32
+        }
+    }
+}
+
+// Returns every channel with its schema and the models that publish and
+// subscribe to it, packed as a MessagePack array of maps
+#[no_mangle]
+pub extern "C" fn list_channels(cb : SizeCallback) -> u32 {
+    unsafe {
+        let channels = SCHEDULERS.get_mut(0).unwrap().list_channels();
+        match rmps::to_vec_named(&channels) {
+            Ok(packed) => {
+                copy_out(&packed, cb);
+                RSISStat::OK as u32
+            },
+            Err(_) => RSISStat::ERR as u32
+        }
+    }
+}
+
+// Delays delivery of the messages on a channel by `latency` sim seconds. A
+// null name sets the latency of numbered channel `id`
+#[no_mangle]
+pub extern "C" fn set_channel_latency(name : *const c_char, id : i64, latency : f64) -> u32 {
+    unsafe {
+        let key = match name.is_null() {
+            true => ChannelKey::ID(id),
+            false => match CStr::from_ptr(name).to_str() {
+                Ok(val) => ChannelKey::NAME(String::from(val)),
+                Err(_) => return RSISStat::BADARG as u32
+            }
+        };
+        match SCHEDULERS.get_mut(0).unwrap().set_channel_latency(key, latency) {
+            Ok(()) => RSISStat::OK as u32,
+            Err(err) => err as u32
+        }
+    }
+}
+
+// Records every channel message to a log file, until `stop_recording` is
+// called or the library is shut down
+#[no_mangle]
+pub extern "C" fn start_recording(path : *const c_char) -> u32 {
+    if path.is_null() {
+        return RSISStat::BADARG as u32;
+    }
+    unsafe {
+        let path_s = match CStr::from_ptr(path).to_str() {
+            Ok(val) => val,
+            Err(_) => return RSISStat::BADARG as u32
+        };
+        match SCHEDULERS.get_mut(0).unwrap().start_recording(path_s) {
+            Ok(()) => RSISStat::OK as u32,
+            Err(err) => {
+                println!("Failed to record channels to {}: {}", path_s, err);
+                RSISStat::ERR as u32
+            }
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn stop_recording() -> u32 {
+    unsafe {
+        match SCHEDULERS.get_mut(0).unwrap().stop_recording() {
+            Ok(()) => RSISStat::OK as u32,
+            Err(err) => {
+                println!("Failed to write channel recording: {}", err);
+                RSISStat::ERR as u32
+            }
+        }
+    }
+}
+
+// Returns every configuration key with its type, default, current value,
+// and the states it can be set in, packed as a MessagePack array of maps
+#[no_mangle]
+pub extern "C" fn list_scheduler_config(cb : SizeCallback) -> u32 {
+    unsafe {
+        let entries = SCHEDULERS.get_mut(0).unwrap().list_config();
+        match rmps::to_vec_named(&entries) {
+            Ok(packed) => {
+                copy_out(&packed, cb);
+                RSISStat::OK as u32
+            },
+            Err(_) => RSISStat::ERR as u32
         }
     }
 }
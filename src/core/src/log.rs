@@ -0,0 +1,359 @@
+
+// Logging service for models. Messages are tagged with their severity, the
+// model that logged them and the sim time, and pushed into a lock-free
+// buffer, so that logging never blocks or allocates on a worker thread. A
+// printer thread drains the buffer and prints the messages in one place,
+// woken by the first message pushed after each drain. Each model
+// may log a limited number of messages per second of sim time, and the number
+// that were suppressed is reported with its next message
+
+use serde::Serialize;
+use std::cell::Cell;
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::thread;
+
+use crate::channel::{RSISInterface, Ring};
+use crate::scheduler::ModelId;
+
+// Messages held until the printer drains them
+pub const LOG_CAPACITY : usize = 4096;
+
+// Bytes of a message kept. Longer messages are cut short
+pub const LOG_MESSAGE_LEN : usize = 240;
+
+#[repr(u32)]
+#[derive(Copy,Clone,PartialEq,PartialOrd,Debug,Serialize)]
+pub enum Severity {
+    DEBUG = 0,
+    INFO  = 1,
+    WARN  = 2,
+    ERROR = 3,
+}
+
+impl Severity {
+    pub fn from_u32(value : u32) -> Option<Severity> {
+        match value {
+            0 => Some(Severity::DEBUG),
+            1 => Some(Severity::INFO),
+            2 => Some(Severity::WARN),
+            3 => Some(Severity::ERROR),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone,Debug,Serialize)]
+pub struct LogRecord {
+    pub severity : Severity,
+    pub model : Option<ModelId>, // None if not logged by a model
+    pub tick : i64,
+    pub time : f64,
+    pub message : String,
+    pub suppressed : u64, // messages from the model dropped by rate limiting since its last
+}
+
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self.model {
+            Some(id) => write!(f, "<t={:.6}, model {}> {:?}: {}", self.time, id, self.severity, self.message)?,
+            None => write!(f, "<t={:.6}> {:?}: {}", self.time, self.severity, self.message)?,
+        }
+        if self.suppressed > 0 {
+            write!(f, " ({} earlier messages suppressed)", self.suppressed)?;
+        }
+        Ok(())
+    }
+}
+
+// A message as held in the buffer, with its text inline
+pub struct LogEntry {
+    pub severity : Severity,
+    pub model : Option<ModelId>,
+    pub tick : i64,
+    pub time : f64,
+    pub suppressed : u64,
+    len : usize,
+    text : [u8; LOG_MESSAGE_LEN],
+}
+
+// Formats a message into an entry without allocating. Text past the end of
+// the entry is dropped, cut at a character
+struct InlineText {
+    len : usize,
+    text : [u8; LOG_MESSAGE_LEN],
+    full : bool,
+}
+
+impl fmt::Write for InlineText {
+    fn write_str(&mut self, s : &str) -> fmt::Result {
+        if self.full {
+            return Ok(());
+        }
+        let mut len = s.len().min(LOG_MESSAGE_LEN - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.text[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        self.full = len < s.len();
+        Ok(())
+    }
+}
+
+impl LogEntry {
+    pub fn new(severity : Severity, model : Option<ModelId>, tick : i64, time : f64, message : &str, suppressed : u64) -> LogEntry {
+        LogEntry::format(severity, model, tick, time, format_args!("{}", message), suppressed)
+    }
+
+    pub fn format(severity : Severity, model : Option<ModelId>, tick : i64, time : f64, message : fmt::Arguments, suppressed : u64) -> LogEntry {
+        let mut text = InlineText {
+            len : 0,
+            text : [0u8; LOG_MESSAGE_LEN],
+            full : false,
+        };
+        let _ = fmt::Write::write_fmt(&mut text, message);
+        LogEntry {
+            severity : severity,
+            model : model,
+            tick : tick,
+            time : time,
+            suppressed : suppressed,
+            len : text.len,
+            text : text.text,
+        }
+    }
+
+    fn record(&self) -> LogRecord {
+        LogRecord {
+            severity : self.severity,
+            model : self.model,
+            tick : self.tick,
+            time : self.time,
+            message : String::from_utf8_lossy(&self.text[..self.len]).into_owned(),
+            suppressed : self.suppressed,
+        }
+    }
+}
+
+// Messages a model has logged in the current second of sim time. Only used
+// by the worker thread the model runs on
+pub struct RateLimit {
+    model : ModelId,
+    start : AtomicU64, // f64 bits
+    count : AtomicU64,
+    suppressed : AtomicU64,
+}
+
+impl RateLimit {
+    pub fn new(model : ModelId) -> RateLimit {
+        RateLimit {
+            model : model,
+            start : AtomicU64::new(f64::NEG_INFINITY.to_bits()),
+            count : AtomicU64::new(0),
+            suppressed : AtomicU64::new(0),
+        }
+    }
+
+    // The number of messages suppressed since the last one let through, or
+    // None if this one is suppressed. Models without a limit are not limited
+    pub fn admit(limits : &[RateLimit], model : ModelId, time : f64, rate : f64) -> Option<u64> {
+        let limit = match limits.iter().find(|limit| limit.model == model) {
+            Some(limit) => limit,
+            None => return Some(0)
+        };
+        // a reset restarts sim time
+        let start = f64::from_bits(limit.start.load(Ordering::Relaxed));
+        if time >= start + 1.0 || time < start {
+            limit.start.store(time.to_bits(), Ordering::Relaxed);
+            limit.count.store(0, Ordering::Relaxed);
+        }
+        if rate > 0.0 && limit.count.load(Ordering::Relaxed) as f64 >= rate {
+            limit.suppressed.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        limit.count.fetch_add(1, Ordering::Relaxed);
+        Some(limit.suppressed.swap(0, Ordering::Relaxed))
+    }
+}
+
+pub struct LogBuffer {
+    ring : Ring<LogEntry>,
+    level : AtomicU32, // lowest severity kept
+    rate : AtomicU64,  // f64 bits. Messages per model per sim second, 0 for no limit
+    dropped : AtomicU64, // lost to a full buffer since the last drain
+    unread : AtomicBool, // set by the first message pushed after a drain
+    // only taken to wake the printer, once per drain. Holds whether the
+    // printer has been told to stop
+    lock : Mutex<bool>,
+    ready : Condvar,
+}
+
+impl LogBuffer {
+    pub fn new() -> LogBuffer {
+        LogBuffer {
+            ring : Ring::new(LOG_CAPACITY),
+            level : AtomicU32::new(Severity::INFO as u32),
+            rate : AtomicU64::new(10.0f64.to_bits()),
+            dropped : AtomicU64::new(0),
+            unread : AtomicBool::new(false),
+            lock : Mutex::new(false),
+            ready : Condvar::new(),
+        }
+    }
+    pub fn level(&self) -> u32 {
+        self.level.load(Ordering::Relaxed)
+    }
+    pub fn set_level(&self, level : u32) {
+        self.level.store(level, Ordering::Relaxed);
+    }
+    pub fn rate(&self) -> f64 {
+        f64::from_bits(self.rate.load(Ordering::Relaxed))
+    }
+    pub fn set_rate(&self, rate : f64) {
+        self.rate.store(rate.to_bits(), Ordering::Relaxed);
+    }
+    pub fn enabled(&self, severity : Severity) -> bool {
+        severity as u32 >= self.level()
+    }
+    // The first message since the buffer was last drained wakes the printer
+    pub fn push(&self, entry : LogEntry) {
+        if self.ring.push(entry).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        if !self.unread.swap(true, Ordering::SeqCst) {
+            let _guard = self.lock.lock().unwrap();
+            self.ready.notify_one();
+        }
+    }
+    // Blocks until there are messages to drain. False once the printer has
+    // been told to stop
+    fn wait(&self) -> bool {
+        let mut stopped = self.lock.lock().unwrap();
+        while !self.unread.load(Ordering::SeqCst) && !*stopped {
+            stopped = self.ready.wait(stopped).unwrap();
+        }
+        !*stopped
+    }
+    fn set_stopped(&self, stopped : bool) {
+        *self.lock.lock().unwrap() = stopped;
+        self.ready.notify_one();
+    }
+    // Takes every buffered message, oldest first
+    pub fn take(&self) -> Vec<LogRecord> {
+        // cleared first, so a message pushed while draining wakes the drainer again
+        self.unread.store(false, Ordering::SeqCst);
+        let mut records = Vec::new();
+        while let Some(entry) = self.ring.pop() {
+            records.push(entry.record());
+        }
+        records
+    }
+    // Prints every buffered message
+    pub fn flush(&self) {
+        for record in self.take() {
+            println!("{}", record);
+        }
+        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            println!("{} log messages were lost to a full buffer", dropped);
+        }
+    }
+}
+
+// Prints log messages as they arrive while a simulation runs. Dropping it
+// prints what is left and stops the thread
+pub struct LogPrinter {
+    logs : Arc<LogBuffer>,
+    thread : Option<thread::JoinHandle<()>>,
+}
+
+impl LogPrinter {
+    pub fn start(logs : Arc<LogBuffer>) -> LogPrinter {
+        logs.set_stopped(false);
+        let buffer = Arc::clone(&logs);
+        LogPrinter {
+            logs : logs,
+            thread : Some(thread::spawn(move|| {
+                while buffer.wait() {
+                    buffer.flush();
+                }
+                buffer.flush();
+            })),
+        }
+    }
+}
+
+impl Drop for LogPrinter {
+    fn drop(&mut self) {
+        self.logs.set_stopped(true);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+thread_local! {
+    // interface of the worker thread, for models built outside this crate
+    // that cannot reach it through as_any
+    static THREAD_INTERFACE : Cell<*const RSISInterface> = Cell::new(std::ptr::null());
+}
+
+// Called by each worker thread with the interface it hands to its models,
+// which outlives every model call
+pub fn set_thread_interface(interface : &RSISInterface) {
+    THREAD_INTERFACE.with(|cell| cell.set(interface));
+}
+
+// False if not called from a worker thread
+pub fn log_from_thread(severity : Severity, message : &str) -> bool {
+    THREAD_INTERFACE.with(|cell| {
+        let interface = cell.get();
+        if interface.is_null() {
+            return false;
+        }
+        unsafe { (*interface).log(severity, message) };
+        true
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn models_are_limited_per_second_of_sim_time() {
+        let limits = [RateLimit::new(4)];
+        let admitted : Vec<_> = (0..5).map(|k| RateLimit::admit(&limits, 4, k as f64 * 0.1, 3.0)).collect();
+        assert_eq!(admitted, vec![Some(0), Some(0), Some(0), None, None]);
+        // the next window reports what was suppressed in the last
+        assert_eq!(RateLimit::admit(&limits, 4, 1.0, 3.0), Some(2));
+        // models without a limit are never suppressed
+        assert!((0..5).all(|_| RateLimit::admit(&limits, 5, 1.0, 3.0) == Some(0)));
+    }
+
+    #[test]
+    fn long_messages_are_cut_at_a_character() {
+        // two bytes a character, with the limit falling inside one
+        let message = format!("a{}", "é".repeat(LOG_MESSAGE_LEN));
+        let record = LogEntry::new(Severity::INFO, None, 0, 0.0, &message, 0).record();
+        assert_eq!(record.message, format!("a{}", "é".repeat(LOG_MESSAGE_LEN / 2 - 1)));
+    }
+
+    #[test]
+    fn the_printer_wakes_for_new_messages() {
+        let logs = LogBuffer::new();
+        thread::scope(|scope| {
+            let waiter = scope.spawn(|| logs.wait());
+            logs.push(LogEntry::new(Severity::INFO, None, 0, 0.0, "hello", 0));
+            assert!(waiter.join().unwrap());
+        });
+        assert_eq!(logs.take().len(), 1);
+        thread::scope(|scope| {
+            let waiter = scope.spawn(|| logs.wait());
+            logs.set_stopped(true);
+            assert!(!waiter.join().unwrap());
+        });
+    }
+}
@@ -0,0 +1,456 @@
+
+extern crate rmp_serde as rmps;
+
+// Channel traffic recording. Every message sent while a recorder is running
+// is passed to a writer thread, so that models never wait on the disk.
+// The log starts with MAGIC, followed by one record per message: a u32
+// length and a MessagePack RecordHeader, then a u64 length and the message
+// bytes, with lengths little endian. The schema of a channel is only written
+// in its first record
+
+use data_buffer::DataBuffer;
+use serde::{Deserialize, Serialize};
+use std::any::TypeId;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::sync::{Arc, mpsc};
+use std::thread;
+
+use rsisappinterface::BaseModel;
+use rsisappinterface::BufferStruct;
+use rsisappinterface::ChannelTx;
+use rsisappinterface::ConfigStatus;
+use rsisappinterface::Framework;
+use rsisappinterface::RuntimeStatus;
+use rsisappinterface::SizeCallback;
+
+use crate::channel::{ChannelKey, ChannelSchema, RSISInterface, SampleWriter};
+use crate::scheduler::ModelId;
+
+const MAGIC : &[u8; 8] = b"RSISREC1";
+
+// Element types that can be restored on replay. Messages of any other type
+// are recorded, but skipped by replay
+fn element_types() -> [(TypeId, &'static str); 10] {
+    [(TypeId::of::<u8>(), "u8"), (TypeId::of::<i8>(), "i8"),
+     (TypeId::of::<u16>(), "u16"), (TypeId::of::<i16>(), "i16"),
+     (TypeId::of::<u32>(), "u32"), (TypeId::of::<i32>(), "i32"),
+     (TypeId::of::<u64>(), "u64"), (TypeId::of::<i64>(), "i64"),
+     (TypeId::of::<f32>(), "f32"), (TypeId::of::<f64>(), "f64")]
+}
+
+// Splits a message into its element type name and bytes
+pub(crate) fn to_bytes(data : DataBuffer) -> (&'static str, usize, Vec<u8>) {
+    let (bytes, element_size, type_id) = data.into_raw_parts();
+    let name = element_types().iter().find(|(id, _)| *id == type_id).map_or("", |(_, name)| *name);
+    (name, element_size, bytes)
+}
+
+// Size in bytes of one of element_types
+pub(crate) fn element_size(element : &str) -> Option<usize> {
+    match element {
+        "u8" | "i8" => Some(1),
+        "u16" | "i16" => Some(2),
+        "u32" | "i32" | "f32" => Some(4),
+        "u64" | "i64" | "f64" => Some(8),
+        _ => None
+    }
+}
+
+pub(crate) fn from_bytes(element : &str, element_size : usize, bytes : Vec<u8>) -> Option<DataBuffer> {
+    let (type_id, _) = element_types().into_iter().find(|(_, name)| *name == element)?;
+    unsafe {
+        Some(DataBuffer::from_raw_parts(bytes, element_size, type_id))
+    }
+}
+
+// The channel a message was sent on
+pub struct RecordedChannel {
+    pub key : ChannelKey,
+    pub schema : Option<ChannelSchema>,
+    pub sample : bool, // a sample channel, rather than a queue
+}
+
+struct Record {
+    channel : Arc<RecordedChannel>,
+    tick : i64,
+    time : f64,
+    sender : Option<ModelId>,
+    data : DataBuffer,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RecordHeader {
+    tick : i64,
+    time : f64,
+    channel : ChannelKey,
+    sender : ModelId, // 0 if not sent by a model
+    schema : Option<ChannelSchema>,
+    sample : bool,
+    element : String, // "" if not one of element_types
+    element_size : usize,
+}
+
+fn write_record<W : Write>(out : &mut W, record : Record, announced : &mut HashSet<ChannelKey>) -> io::Result<()> {
+    let first = !announced.contains(&record.channel.key);
+    if first {
+        announced.insert(record.channel.key.clone());
+    }
+    let (element, element_size, bytes) = to_bytes(record.data);
+    let header = RecordHeader {
+        tick : record.tick,
+        time : record.time,
+        channel : record.channel.key.clone(),
+        sender : record.sender.unwrap_or(0),
+        schema : if first { record.channel.schema.clone() } else { None },
+        sample : record.channel.sample,
+        element : String::from(element),
+        element_size : element_size,
+    };
+    let packed = rmps::to_vec_named(&header).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    out.write_all(&(packed.len() as u32).to_le_bytes())?;
+    out.write_all(&packed)?;
+    out.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    out.write_all(&bytes)
+}
+
+// Senders can be shared between threads, so worker threads record through
+// the same one without taking a lock
+pub struct Recorder {
+    tx : Option<mpsc::Sender<Record>>,
+    writer : Option<thread::JoinHandle<io::Result<()>>>,
+}
+
+impl Recorder {
+    pub fn create(path : &str) -> io::Result<Recorder> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        let (tx, rx) = mpsc::channel();
+        let writer = thread::spawn(move|| {
+            let mut announced = HashSet::new();
+            for record in rx {
+                write_record(&mut out, record, &mut announced)?;
+            }
+            out.flush()
+        });
+        Ok(Recorder {
+            tx : Some(tx),
+            writer : Some(writer),
+        })
+    }
+
+    pub fn record(&self, channel : &Arc<RecordedChannel>, tick : i64, time : f64, sender : Option<ModelId>, data : &DataBuffer) {
+        if let Some(tx) = &self.tx {
+            // fails only if the writer stopped on an error, which finish reports
+            let _ = tx.send(Record {
+                channel : Arc::clone(channel),
+                tick : tick,
+                time : time,
+                sender : sender,
+                data : data.clone(),
+            });
+        }
+    }
+
+    // Waits for every message recorded so far to be written
+    pub fn finish(mut self) -> io::Result<()> {
+        self.close()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.tx.take();
+        match self.writer.take() {
+            Some(writer) => match writer.join() {
+                Ok(result) => result,
+                Err(_) => Err(io::Error::new(io::ErrorKind::Other, "recorder thread panicked"))
+            },
+            None => Ok(())
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Err(err) = self.close() {
+            println!("Failed to write channel recording: {}", err);
+        }
+    }
+}
+
+pub struct RecordedMessage {
+    pub tick : i64,
+    pub time : f64,
+    pub channel : ChannelKey,
+    pub sender : Option<ModelId>,
+    pub data : DataBuffer,
+}
+
+pub struct RecordLog {
+    pub messages : Vec<RecordedMessage>,
+    pub channels : HashMap<ChannelKey, RecordedChannel>,
+}
+
+fn partial_record() {
+    println!("Channel recording ends with a partial record");
+}
+
+// False at the end of the log. A log cut short by a crash is read up to its
+// last whole record
+fn read_exact_or_end<R : Read>(input : &mut R, buf : &mut [u8], at_start : bool) -> io::Result<bool> {
+    match input.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+            if !at_start {
+                partial_record();
+            }
+            Ok(false)
+        },
+        Err(err) => Err(err)
+    }
+}
+
+// Reads a field of `len` bytes, None at the end of the log. The buffer only
+// grows with the bytes actually read, so a corrupt length cannot make it
+// allocate more than the file holds
+fn read_field_or_end<R : Read>(input : &mut R, len : u64) -> io::Result<Option<Vec<u8>>> {
+    let mut buf = Vec::new();
+    input.by_ref().take(len).read_to_end(&mut buf)?;
+    if buf.len() as u64 != len {
+        partial_record();
+        return Ok(None);
+    }
+    Ok(Some(buf))
+}
+
+pub fn read_log(path : &str) -> io::Result<RecordLog> {
+    let mut input = BufReader::new(File::open(path)?);
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a channel recording"));
+    }
+    let mut log = RecordLog {
+        messages : Vec::new(),
+        channels : HashMap::new(),
+    };
+    let mut skipped = 0;
+    loop {
+        let mut len = [0u8; 4];
+        if !read_exact_or_end(&mut input, &mut len, true)? {
+            break;
+        }
+        let packed = match read_field_or_end(&mut input, u32::from_le_bytes(len) as u64)? {
+            Some(packed) => packed,
+            None => break
+        };
+        let header : RecordHeader = rmps::from_slice(&packed).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let mut len = [0u8; 8];
+        if !read_exact_or_end(&mut input, &mut len, false)? {
+            break;
+        }
+        let bytes = match read_field_or_end(&mut input, u64::from_le_bytes(len))? {
+            Some(bytes) => bytes,
+            None => break
+        };
+        if header.schema.is_some() || !log.channels.contains_key(&header.channel) {
+            log.channels.insert(header.channel.clone(), RecordedChannel {
+                key : header.channel.clone(),
+                schema : header.schema,
+                sample : header.sample,
+            });
+        }
+        match from_bytes(&header.element, header.element_size, bytes) {
+            Some(data) => log.messages.push(RecordedMessage {
+                tick : header.tick,
+                time : header.time,
+                channel : header.channel,
+                sender : if header.sender == 0 { None } else { Some(header.sender) },
+                data : data,
+            }),
+            None => skipped += 1
+        }
+    }
+    if skipped > 0 {
+        println!("Skipped {} recorded messages of unknown element type", skipped);
+    }
+    Ok(log)
+}
+
+enum ReplayTx {
+    QUEUE(Box<dyn ChannelTx>),
+    SAMPLE(SampleWriter),
+}
+
+// Model that sends the messages recorded on one channel back into it. Each
+// message is sent in the first frame at or after the sim time it was
+// recorded at, and the replay starts over each time it is initialized.
+// Replays are implemented via the BaseModel trait interface, so they are
+// scheduled the same way as the model they stand in for
+pub struct Replay {
+    channel : RecordedChannel,
+    messages : Vec<RecordedMessage>,
+    next : usize, // first message not yet sent
+    tx : Option<ReplayTx>,
+}
+
+impl Replay {
+    pub fn open(path : &str, key : ChannelKey) -> io::Result<Replay> {
+        let mut log = read_log(path)?;
+        let channel = match log.channels.remove(&key) {
+            Some(channel) => channel,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "channel is not in the recording"))
+        };
+        let mut messages : Vec<_> = log.messages.into_iter().filter(|msg| msg.channel == key).collect();
+        // threads record independently, so messages are only ordered per thread
+        messages.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Ok(Replay {
+            channel : channel,
+            messages : messages,
+            next : 0,
+            tx : None,
+        })
+    }
+
+    fn request_tx(&self, interface : &mut Box<dyn Framework>) -> Option<ReplayTx> {
+        if let ChannelKey::ID(id) = self.channel.key {
+            return Some(ReplayTx::QUEUE(interface.request_tx(id)));
+        }
+        let (name, schema) = match (&self.channel.key, &self.channel.schema) {
+            (ChannelKey::NAME(name), Some(schema)) => (name, schema),
+            _ => return None
+        };
+        let rsis = interface.as_any().downcast_ref::<RSISInterface>()?;
+        let result = match self.channel.sample {
+            true => rsis.request_sample_writer(name, schema).map(ReplayTx::SAMPLE),
+            false => rsis.request_named_tx(name, schema).map(ReplayTx::QUEUE),
+        };
+        match result {
+            Ok(tx) => Some(tx),
+            Err(err) => {
+                println!("Replay could not open channel {}: {:?}", name, err);
+                None
+            }
+        }
+    }
+}
+
+impl BaseModel for Replay {
+    fn config(&mut self) -> ConfigStatus {
+        ConfigStatus::OK
+    }
+    fn init(&mut self, interface : &mut Box<dyn Framework>) -> RuntimeStatus {
+        self.next = 0;
+        self.tx = self.request_tx(interface);
+        match self.tx {
+            Some(_) => RuntimeStatus::OK,
+            None => RuntimeStatus::ERROR
+        }
+    }
+    fn step(&mut self, interface : &mut Box<dyn Framework>) -> RuntimeStatus {
+        let time = interface.get_simtime();
+        while self.next < self.messages.len() && self.messages[self.next].time <= time + 1e-9 {
+            let data = self.messages[self.next].data.clone();
+            self.next += 1;
+            match &mut self.tx {
+                // a full subscriber queue is counted by the channel
+                Some(ReplayTx::QUEUE(tx)) => { let _ = tx.send(data); },
+                Some(ReplayTx::SAMPLE(writer)) => writer.write(data),
+                None => return RuntimeStatus::ERROR
+            }
+        }
+        RuntimeStatus::OK
+    }
+    fn pause(&mut self) -> RuntimeStatus {
+        RuntimeStatus::OK
+    }
+    fn stop(&mut self) -> RuntimeStatus {
+        RuntimeStatus::OK
+    }
+    fn msg_get(&self, _id : BufferStruct, _cb : SizeCallback) -> u32 {
+        1
+    }
+    fn msg_set(&mut self, _id : BufferStruct, _data : BufferStruct) -> u32 {
+        1
+    }
+    fn get_ptr(&self, _id : BufferStruct) -> *const u8 {
+        0 as *const u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epoch::FrameClock;
+    use std::sync::mpsc::TryRecvError;
+
+    fn log_path(name : &str) -> String {
+        let path = std::env::temp_dir().join(format!("rsis-recorder-{}-{}.log", std::process::id(), name));
+        String::from(path.to_str().unwrap())
+    }
+
+    fn schema() -> ChannelSchema {
+        ChannelSchema::ARRAY { element : String::from("f64"), shape : vec![2] }
+    }
+
+    fn values(data : &[f64]) -> Vec<u8> {
+        data.iter().flat_map(|val| val.to_ne_bytes()).collect()
+    }
+
+    // Records two messages on channel "cmd", a frame apart
+    fn record(path : &str) {
+        let clock = Arc::new(FrameClock::new(10.0, 10.0));
+        let interface = RSISInterface::new().for_thread(Arc::clone(&clock), &[]);
+        interface.start_recording(path).unwrap();
+        let mut tx = interface.request_named_tx("cmd", &schema()).unwrap();
+        tx.send(DataBuffer::from_vec(vec![1.0f64, 2.0])).unwrap();
+        clock.advance();
+        tx.send(DataBuffer::from_vec(vec![3.0f64, 4.0])).unwrap();
+        interface.stop_recording().unwrap();
+    }
+
+    #[test]
+    fn recorded_messages_read_back_and_replay() {
+        let path = log_path("round-trip");
+        record(&path);
+        let log = read_log(&path).unwrap();
+        let key = ChannelKey::NAME(String::from("cmd"));
+        assert_eq!(log.messages.len(), 2);
+        assert_eq!((log.messages[1].tick, log.messages[1].channel.clone()), (1, key.clone()));
+        assert!((log.messages[1].time - 0.1).abs() < 1e-9);
+        assert_eq!(log.messages[1].data.raw_data(), &values(&[3.0, 4.0])[..]);
+        assert!(log.channels[&key].schema == Some(schema()));
+
+        // each message is sent again in the frame it was recorded in
+        let clock = Arc::new(FrameClock::new(10.0, 10.0));
+        let mut framework : Box<dyn Framework> = Box::new(RSISInterface::new().for_thread(Arc::clone(&clock), &[]));
+        let mut rx = framework.as_any().downcast_ref::<RSISInterface>().unwrap().request_named_rx("cmd", &schema()).unwrap();
+        let mut replay = Replay::open(&path, key).unwrap();
+        assert!(matches!(replay.init(&mut framework), RuntimeStatus::OK));
+        assert!(matches!(replay.step(&mut framework), RuntimeStatus::OK));
+        assert_eq!(rx.try_recv().unwrap().raw_data(), &values(&[1.0, 2.0])[..]);
+        assert_eq!(rx.try_recv().err(), Some(TryRecvError::Empty));
+        clock.advance();
+        assert!(matches!(replay.step(&mut framework), RuntimeStatus::OK));
+        assert_eq!(rx.try_recv().unwrap().raw_data(), &values(&[3.0, 4.0])[..]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn cut_short_logs_read_up_to_the_last_whole_record() {
+        let path = log_path("partial");
+        record(&path);
+        let len = std::fs::metadata(&path).unwrap().len();
+        File::options().write(true).open(&path).unwrap().set_len(len - 4).unwrap();
+        assert_eq!(read_log(&path).unwrap().messages.len(), 1);
+
+        // a corrupt length is not trusted for the size of the read
+        let mut out = File::create(&path).unwrap();
+        out.write_all(MAGIC).unwrap();
+        out.write_all(&u32::MAX.to_le_bytes()).unwrap();
+        out.write_all(&[0u8; 16]).unwrap();
+        drop(out);
+        assert_eq!(read_log(&path).unwrap().messages.len(), 0);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,19 +1,38 @@
 
 extern crate rsisappinterface;
-extern crate rmp_serde as rmps;
 
 use crate::scheduler::SchedulerState;
+use crate::scheduler::StateSignal;
 use crate::scheduler::Scheduler;
 use crate::scheduler::ScheduledObject;
+use crate::scheduler::ModelId;
+use crate::scheduler::ModelControl;
+use crate::scheduler::ScheduleError;
+use crate::scheduler::ModelFault;
+use crate::scheduler::FaultKind;
+use crate::scheduler::FaultLog;
+use crate::scheduler::panic_message;
 
+use crate::config::{ConfigKey, ConfigValue, ConfigError, ConfigEntry};
 use crate::channel::RSISInterface;
+use crate::channel::{ChannelError, ChannelInfo, ChannelKey};
+use crate::epoch::FrameClock;
+use crate::hooks::FrameHook;
+use crate::clock::{ClockSource, MonotonicClock};
+use crate::watchdog::{Heartbeat, Phase, Watchdog};
+use crate::log;
+use crate::log::{LogPrinter, Severity};
 
 pub use std::ffi::c_void;
 use rsisappinterface::BaseModel;
 use rsisappinterface::RuntimeStatus;
 use rsisappinterface::Framework;
 use std::{thread,time};
-use std::sync::{Arc, Barrier, mpsc, mpsc::TryRecvError, mpsc::Receiver, mpsc::Sender, Mutex};
+use std::fmt;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Barrier, mpsc, mpsc::Sender};
 
 #[derive(Copy,Clone,PartialEq)]
 pub enum ThreadCommand {
@@ -23,363 +42,821 @@ pub enum ThreadCommand {
     SHUTDOWN
 }
 
+// What failed on a worker thread, by its position in the thread
+#[derive(Copy,Clone,PartialEq)]
+pub enum FaultSource {
+    MODEL(u32),
+    HOOK(u32), // frame hook
+}
+
+impl fmt::Display for FaultSource {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FaultSource::MODEL(idx) => write!(f, "app {}", idx),
+            FaultSource::HOOK(idx) => write!(f, "frame hook {}", idx),
+        }
+    }
+}
+
 #[derive(Copy,Clone,PartialEq)]
 pub enum ThreadResult {
     OK(ThreadCommand),
-    ERR(ThreadCommand, u32),
+    ERR(ThreadCommand, FaultSource),
     END
 }
 
+// Everything the runner thread reacts to arrives on a single channel, so it
+// can block until there is something to do
+#[derive(Copy,Clone,PartialEq)]
+pub enum RunnerEvent {
+    COMMAND(ThreadCommand),
+    RESULT(usize, ThreadResult), // thread index, result
+    EXITED(usize),               // thread index
+    TIMEOUT(usize),              // thread index, reported by the watchdog
+}
+
+// Models and hooks handed back by a worker thread when it exits
+pub type ThreadContents = (Vec<ScheduledObject>, Vec<Box<dyn FrameHook + Send>>);
+
 pub struct ThreadState {
     pub frequency : f64,
     pub models : Vec<ScheduledObject>,
+    pub hooks : Vec<Box<dyn FrameHook + Send>>,
+}
+
+// Scheduler side reference to a scheduled model
+struct ScheduledHandle {
+    thread  : usize,
+    control : Arc<ModelControl>,
+}
+
+// Ratio of sim time to wall clock time that frames are paced at. Shared
+// with the worker threads, which read it at the start of every step, so
+// that it can be changed while paused. Zero means no pacing, and the
+// simulation runs as fast as possible
+pub struct RealTimeScale(AtomicU64);
+
+impl RealTimeScale {
+    fn new() -> RealTimeScale {
+        RealTimeScale(AtomicU64::new(0.0f64.to_bits()))
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Acquire))
+    }
+
+    // An infinite scale is the same as no pacing
+    fn set(&self, scale : f64) -> bool {
+        if scale.is_nan() || scale < 0.0 {
+            return false;
+        }
+        let scale = if scale.is_infinite() { 0.0 } else { scale };
+        self.0.store(scale.to_bits(), Ordering::Release);
+        true
+    }
 }
 
 //
-// Implements an optional soft-real-time scheduling,
+// Implements optional scaled real-time scheduling,
 // where the std::thread::sleep call is used to halt the
 // thread for a specific period of time
 pub struct NRTScheduler {
     pub threads : Vec<ThreadState>,
-    pub handles : Vec<thread::JoinHandle<()>>,
-    pub state   : Arc<Mutex<SchedulerState>>,
+    pub handles : Vec<thread::JoinHandle<ThreadContents>>,
+    pub state   : Arc<StateSignal>,
 
     pub runner : Option<thread::JoinHandle<()>>,
-    pub runner_tx : Option<Sender<ThreadCommand>>,
-    pub runner_rx : Option<Receiver<ThreadResult>>,
+    pub runner_tx : Option<Sender<RunnerEvent>>,
 
     pub interface : RSISInterface,
 
+    next_id : ModelId,
+    // model controls stay reachable after the models are moved into worker threads
+    controls : HashMap<ModelId, ScheduledHandle>,
+    faults : Arc<FaultLog>,
+    watchdog : Option<Watchdog>,
+    printer : Option<LogPrinter>, // prints model log messages while running
+
     // parameters
-    pub soft_real_time : bool, // if true, enable soft real-time behavior
+    pub realtime_scale : Arc<RealTimeScale>, // sim seconds per wall clock second
+    pub watchdog_deadline : f64, // seconds a model call may run for. Disabled if not positive
+    clock : Arc<dyn ClockSource>, // paces frames
+}
+
+// Every worker thread is handed an RSISInterface as its Framework
+fn rsis_interface(interface : &Box<dyn Framework>) -> &RSISInterface {
+    match interface.as_any().downcast_ref::<RSISInterface>() {
+        Some(intf) => intf,
+        None => panic!("Something went terribly wrong. Bad Framework instantiation")
+    }
 }
 
-fn time_to_next_frame(start : time::Instant, width : time::Duration) -> time::Duration {
-    let now = time::Instant::now();
-    let dur = now - start;
-    if width < dur {
-        println!("BAD DURATION: {width:#?} {dur:#?}");
-        return time::Duration::ZERO;
+// Sleeps until the end of a paced frame, logging frames that overran
+fn wait_for_frame(clock : &dyn ClockSource, frame_end : f64, stop : &AtomicBool, monitor : &WorkerMonitor, interface : &RSISInterface) {
+    let now = clock.now();
+    if now > frame_end {
+        interface.log_fmt(Severity::WARN, format_args!("BAD DURATION: frame overran by {:.6} [s]", now - frame_end));
     } else {
-        return width - dur;
+        let remaining = time::Duration::from_secs_f64(frame_end - now);
+        monitor.waiting(Phase::SLEEP, remaining, || clock.sleep_until(frame_end, stop));
+    }
+}
+
+// A model runs every `divisor` frames, starting `offset` frames in
+fn validate_rate(divisor : i64, offset : i64) -> Result<(), ScheduleError> {
+    if divisor <= 0 {
+        return Err(ScheduleError::BADDIVISOR);
+    }
+    if offset < 0 || offset >= divisor {
+        return Err(ScheduleError::BADOFFSET);
+    }
+    Ok(())
+}
+
+const PACING_STATES : [SchedulerState; 3] = [SchedulerState::CONFIG, SchedulerState::INITIALIZED, SchedulerState::PAUSED];
+
+// Keys accepted by `NRTScheduler::config`
+const NRT_CONFIG : [ConfigKey; 5] = [
+    ConfigKey {
+        name    : "srt",
+        doc     : "Paces frames in real time. Shorthand for an rtscale of 1, or 0 when false",
+        default : ConfigValue::BOOL(false),
+        states  : &PACING_STATES,
+    },
+    ConfigKey {
+        name    : "rtscale",
+        doc     : "Sim seconds run per wall clock second. 0 or Inf runs as fast as possible",
+        default : ConfigValue::FLOAT(0.0),
+        states  : &PACING_STATES,
+    },
+    ConfigKey {
+        name    : "watchdog",
+        doc     : "Seconds a model call may run for before the scheduler times out. 0 disables the watchdog",
+        default : ConfigValue::FLOAT(0.0),
+        states  : &[SchedulerState::CONFIG],
+    },
+    ConfigKey {
+        name    : "loglevel",
+        doc     : "Lowest severity of model log messages kept. 0 DEBUG, 1 INFO, 2 WARN, 3 ERROR",
+        default : ConfigValue::INT(1),
+        states  : &PACING_STATES,
+    },
+    ConfigKey {
+        name    : "lograte",
+        doc     : "Log messages each model may write per second of sim time. 0 disables the limit",
+        default : ConfigValue::FLOAT(10.0),
+        states  : &PACING_STATES,
+    },
+];
+
+// Seconds to wait for threads to exit when resetting the scheduler
+pub const SHUTDOWN_TIMEOUT : f64 = 5.0;
+
+// Tolerance used when comparing frame times measured in sim ticks
+const TICK_EPSILON : f64 = 1e-9;
+
+// Returns the least common multiple of the thread frame periods, in sim
+// ticks. Only exists when every thread runs a whole number of ticks per frame
+fn hyperperiod_ticks(threads : &Vec<ThreadState>, base_frequency : f64) -> Option<u64> {
+    let mut hyperperiod : u64 = 1;
+    for ts in threads.iter() {
+        let ticks = base_frequency / ts.frequency;
+        if !ticks.is_finite() || (ticks - ticks.round()).abs() > TICK_EPSILON {
+            return None;
+        }
+        let ticks = ticks.round() as u64;
+        let mut a = hyperperiod;
+        let mut b = ticks;
+        while b != 0 {
+            let t = a % b;
+            a = b;
+            b = t;
+        }
+        hyperperiod = hyperperiod / a * ticks;
+    }
+    Some(hyperperiod)
+}
+
+// Synchronization shared by all worker threads. Pause requests are only
+// acted on at a sync point, where every thread is at the same sim time
+struct FrameSync {
+    barrier : Barrier,
+    pause_request : AtomicBool,
+    pause_now : AtomicBool,
+}
+
+impl FrameSync {
+    fn new(threads : usize) -> FrameSync {
+        FrameSync {
+            barrier : Barrier::new(threads),
+            pause_request : AtomicBool::new(false),
+            pause_now : AtomicBool::new(false),
+        }
+    }
+
+    // Waits for all threads, and returns whether they should all pause. The
+    // decision is made once by the barrier leader so every thread agrees
+    fn wait(&self) -> bool {
+        if self.barrier.wait().is_leader() {
+            self.pause_now.store(self.pause_request.load(Ordering::Acquire), Ordering::Release);
+        }
+        self.barrier.wait();
+        self.pause_now.load(Ordering::Acquire)
+    }
+
+    // Asks every thread to pause at the next sync point, waking any that are
+    // sleeping out a paced frame
+    fn request_pause(&self, clock : &dyn ClockSource) {
+        self.pause_request.store(true, Ordering::Release);
+        clock.wake();
     }
 }
 
 fn send_cmd_to_threads(handles : &mut Vec::<Sender<ThreadCommand>>, cmd : ThreadCommand) {
     for tx in handles.iter_mut() {
-        tx.send(cmd).unwrap();
+        // a thread that has already ended is not an error here
+        let _ = tx.send(cmd);
+    }
+}
+
+// Sends SHUTDOWN to the highest numbered thread that is still running, once
+// the previous one has ended, so that threads are stopped in reverse order
+fn shutdown_next_thread(handles : &mut Vec::<Sender<ThreadCommand>>, ended : &Vec<bool>, sent : &mut Vec<bool>) {
+    if (0..handles.len()).any(|pos| sent[pos] && !ended[pos]) {
+        return; // still waiting on a thread
+    }
+    if let Some(pos) = (0..handles.len()).rev().find(|&pos| !sent[pos] && !ended[pos]) {
+        sent[pos] = true;
+        let _ = handles[pos].send(ThreadCommand::SHUTDOWN);
+    }
+}
+
+// Reports the model calls made by a worker thread
+struct WorkerMonitor {
+    pos       : usize,
+    faults    : Arc<FaultLog>,
+    heartbeat : Arc<Heartbeat>,
+}
+
+impl WorkerMonitor {
+    // Runs a model or hook call. A panic is caught and recorded, and None is
+    // returned in place of the call's result
+    fn guarded<T, F>(&self, model : Option<ModelId>, phase : Phase, f : F) -> Option<T>
+        where F : FnOnce() -> T
+    {
+        self.heartbeat.enter(model, phase);
+        let result = panic::catch_unwind(AssertUnwindSafe(f));
+        self.heartbeat.leave();
+        match result {
+            Ok(val) => Some(val),
+            Err(payload) => {
+                self.faults.record(ModelFault {
+                    kind    : FaultKind::PANIC,
+                    thread  : self.pos,
+                    model   : model,
+                    phase   : phase.name(),
+                    message : panic_message(&payload),
+                });
+                None
+            }
+        }
+    }
+
+    // Runs a wait of the scheduler itself, which the watchdog also watches.
+    // The deadline starts once `allowance` has passed
+    fn waiting<T, F>(&self, phase : Phase, allowance : time::Duration, f : F) -> T
+        where F : FnOnce() -> T
+    {
+        self.heartbeat.enter_with_allowance(None, phase, allowance);
+        let result = f();
+        self.heartbeat.leave();
+        result
+    }
+}
+
+// Models are stopped in the reverse of their scheduled order
+fn stop_models(models : &mut Vec<ScheduledObject>, monitor : &WorkerMonitor) {
+    for obj in models.iter_mut().rev() {
+        match monitor.guarded(Some(obj.id), Phase::STOP, || obj.model.stop()) {
+            Some(RuntimeStatus::ERROR) => println!("Model {} errored while stopping", obj.id),
+            _ => ()
+        }
+    }
+}
+
+// Blocks until a thread has finished, or the deadline passes
+fn wait_for_exit<T>(handle : &thread::JoinHandle<T>, deadline : Option<time::Instant>) -> bool {
+    while !handle.is_finished() {
+        if let Some(end) = deadline {
+            if time::Instant::now() >= end {
+                return false;
+            }
+        }
+        thread::sleep(time::Duration::from_millis(1));
+    }
+    true
+}
+
+// Reports a worker thread that unwound without sending a result, so that the
+// runner does not wait on it forever
+struct WorkerGuard {
+    pos : usize,
+    tx  : Sender<RunnerEvent>,
+}
+
+impl Drop for WorkerGuard {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            let _ = self.tx.send(RunnerEvent::EXITED(self.pos));
+        }
     }
 }
 
 impl NRTScheduler {
-    fn start_runner(&mut self) -> (Sender<ThreadCommand>, Receiver<ThreadResult>) {
-        let (mtor_tx, mtor_rx) = mpsc::channel();
-        let (_rtom_tx, rtom_rx) = mpsc::channel();
+    fn start_runner(&mut self) -> Sender<RunnerEvent> {
+        let (event_tx, event_rx) = mpsc::channel();
         let threadlen = self.threads.len();
         
-        // create threads now. Add 1 for main thread
-        let mut thread_state = Vec::<SchedulerState>::new();
+        // create threads now
         let mut tx_handles = Vec::<Sender<ThreadCommand>>::new();
-        let mut rx_handles = Vec::<Receiver<ThreadResult>>::new();
-        let barrier = Arc::new(Barrier::new(threadlen));
-        for ts in &mut self.threads[..] {
-            let cbarrier = Arc::clone(&barrier);
-            let mut interface : Box<dyn Framework> = Box::new(RSISInterface::clone(&self.interface));
+        // threads keep their own frame clocks. Sim steps are counted in ticks
+        // of the fastest thread, and threads only wait on each other at the
+        // hyperperiod, if one exists
+        let base_frequency = self.threads.iter().fold(0.0, |acc, ts| f64::max(acc, ts.frequency));
+        let hyperperiod = hyperperiod_ticks(&self.threads, base_frequency);
+        let sync = Arc::new(FrameSync::new(threadlen));
+        let heartbeats : Vec<_> = (0..threadlen).map(|_| Arc::new(Heartbeat::new())).collect();
+        let frame_clocks : Vec<_> = self.threads.iter().map(|ts| Arc::new(FrameClock::new(ts.frequency, base_frequency))).collect();
+        if self.watchdog_deadline > 0.0 {
+            let deadline = time::Duration::from_secs_f64(self.watchdog_deadline);
+            self.watchdog = Some(Watchdog::start(heartbeats.clone(), deadline, Arc::clone(&self.faults), event_tx.clone()));
+        }
+        for (pos, ts) in self.threads.iter_mut().enumerate() {
+            let csync = Arc::clone(&sync);
+            let monitor = WorkerMonitor {
+                pos       : pos,
+                faults    : Arc::clone(&self.faults),
+                heartbeat : Arc::clone(&heartbeats[pos]),
+            };
+            let frame_clock = Arc::clone(&frame_clocks[pos]);
             let mut u: Vec<_> = ts.models.drain(..).collect();
+            let ids : Vec<_> = u.iter().map(|obj| obj.id).collect();
+            let mut interface : Box<dyn Framework> = Box::new(self.interface.for_thread(Arc::clone(&frame_clock), &ids));
+            let mut hooks: Vec<_> = ts.hooks.drain(..).collect();
             let (txx, rxx) = mpsc::channel(); // trigger channel
-            let (tx, rx)   = mpsc::channel(); // response channel
+            let tx = event_tx.clone();        // response channel
 
-            let srt = self.soft_real_time; // passed to closure
+            let rt_scale = Arc::clone(&self.realtime_scale);
+            let clock = Arc::clone(&self.clock);
             let frame_dur = 1.0 / ts.frequency;
-            let frame_sec = frame_dur.trunc();
-            let frame_ns  = (frame_dur - frame_sec) * 1e9;
-            let frame_width = time::Duration::new(frame_sec as u64, frame_ns as u32);
+            let frame_ticks = base_frequency / ts.frequency; // sim ticks per frame
 
             self.handles.push(thread::spawn(move|| {
+                let _guard = WorkerGuard { pos : pos, tx : tx.clone() };
+                log::set_thread_interface(rsis_interface(&interface));
+                let mut frame : i64 = 0; // frames executed by this thread
+                let mut target : f64 = 0.0; // sim tick to run until
+                let mut synced : u64 = 0; // last hyperperiod boundary met, in sim ticks
+                // set once a model has panicked. The thread no longer runs
+                // its models, but still answers commands until shut down
+                let mut faulted = false;
                 loop {
                     match rxx.recv() {
                         Ok(ThreadCommand::INIT) => {
-                            let mut ii = 0;
-                            for obj in &mut u[..] {
-                                match (*obj).model.init(&mut interface) {
-                                    RuntimeStatus::ERROR => {
-                                        tx.send(ThreadResult::ERR(ThreadCommand::INIT, ii));
+                            let mut result = ThreadResult::OK(ThreadCommand::INIT);
+                            for (ii, obj) in u.iter_mut().enumerate() {
+                                let id = obj.id;
+                                rsis_interface(&interface).set_caller(Some(id));
+                                match monitor.guarded(Some(id), Phase::INIT, || obj.model.init(&mut interface)) {
+                                    Some(RuntimeStatus::ERROR) => {
+                                        result = ThreadResult::ERR(ThreadCommand::INIT, FaultSource::MODEL(ii as u32));
+                                        break;
+                                    },
+                                    None => {
+                                        faulted = true;
+                                        result = ThreadResult::ERR(ThreadCommand::INIT, FaultSource::MODEL(ii as u32));
                                         break;
                                     },
                                     _ => ()
                                 }
-                                ii += 1;
                             }
-                            tx.send(ThreadResult::OK(ThreadCommand::INIT)).unwrap();
+                            rsis_interface(&interface).set_caller(None);
+                            let _ = tx.send(RunnerEvent::RESULT(pos, result));
                         },
                         Ok(ThreadCommand::EXECUTE(value)) => {
-                            let mut halt_sim = false;
-                            for _ in 0..value {
-                                let framestart = time::Instant::now();
+                            let mut halt_sim = faulted;
+                            let mut fault = FaultSource::MODEL(0); // model or hook that panicked
+                            let run_start = clock.now();
+                            let scale = rt_scale.get();
+                            let run_frame = frame;
+                            target += value as f64;
+                            loop {
+                                let frontier = frame as f64 * frame_ticks;
+                                // every thread meets at each hyperperiod boundary up to the
+                                // target before running past it, however the target was
+                                // reached, so that no thread waits on one that has moved on
+                                if let Some(ticks) = hyperperiod {
+                                    let boundary = (synced + ticks) as f64;
+                                    if boundary <= frontier + TICK_EPSILON && boundary <= target + TICK_EPSILON {
+                                        synced += ticks;
+                                        let pause = monitor.waiting(Phase::SYNC, time::Duration::ZERO, || csync.wait());
+                                        // every thread has now finished the frames before the boundary
+                                        frame_clock.set_horizon(synced as f64 / base_frequency);
+                                        if pause {
+                                            // the next step continues from the boundary
+                                            target = frontier;
+                                            break;
+                                        }
+                                        continue;
+                                    }
+                                }
+                                if frontier >= target - TICK_EPSILON {
+                                    break;
+                                }
+                                // apply model requests at the frame boundary
                                 for obj in &mut u[..] {
-                                    if (*obj).counter == 0 {
-                                        match (*obj).model.step(&mut interface) {
-                                            RuntimeStatus::ERROR => {
-                                                println!("App errored");
+                                    (*obj).apply_requests(frame);
+                                }
+                                frame += 1;
+                                // once halted, the thread keeps counting frames until the
+                                // next sync point so that the other threads are released
+                                if !halt_sim {
+                                    rsis_interface(&interface).set_caller(None);
+                                    for (ii, hook) in hooks.iter_mut().enumerate() {
+                                        match monitor.guarded(None, Phase::FRAMESTART, || hook.frame_start(&mut interface)) {
+                                            Some(RuntimeStatus::OK) => (),
+                                            Some(_) => {
+                                                println!("Frame start hook errored");
                                                 halt_sim = true;
-                                                break;
                                             },
-                                            RuntimeStatus::FINISHED => {
-                                                println!("Halting simulation");
+                                            None => {
+                                                faulted = true;
+                                                fault = FaultSource::HOOK(ii as u32);
                                                 halt_sim = true;
                                                 break;
-                                            },
-                                            RuntimeStatus::OK => ()
+                                            }
                                         }
                                     }
-                                    (*obj).counter += 1;
-                                    if (*obj).counter == (*obj).divisor {
-                                        (*obj).counter = 0;
+                                    for (ii, obj) in u.iter_mut().enumerate() {
+                                        // models do not run in a frame whose start hooks failed
+                                        if faulted || halt_sim {
+                                            break;
+                                        }
+                                        if (*obj).counter == 0 && (*obj).enabled {
+                                            let id = obj.id;
+                                            rsis_interface(&interface).set_caller(Some(id));
+                                            match monitor.guarded(Some(id), Phase::STEP, || obj.model.step(&mut interface)) {
+                                                Some(RuntimeStatus::ERROR) => {
+                                                    println!("App errored");
+                                                    halt_sim = true;
+                                                    break;
+                                                },
+                                                Some(RuntimeStatus::FINISHED) => {
+                                                    println!("Halting simulation");
+                                                    halt_sim = true;
+                                                    break;
+                                                },
+                                                Some(RuntimeStatus::OK) => (),
+                                                None => {
+                                                    faulted = true;
+                                                    fault = FaultSource::MODEL(ii as u32);
+                                                    halt_sim = true;
+                                                    break;
+                                                }
+                                            }
+                                        }
+                                        (*obj).counter += 1;
+                                        if (*obj).counter == (*obj).divisor {
+                                            (*obj).counter = 0;
+                                        }
                                     }
-                                }
-                                // Check for pause command
-                                match rxx.try_recv() {
-                                    Ok(ThreadCommand::PAUSE) => {
-                                        for obj in &mut u[..] {
-                                            (*obj).model.pause();
+                                    rsis_interface(&interface).set_caller(None);
+                                    for (ii, hook) in hooks.iter_mut().enumerate() {
+                                        if faulted {
+                                            break;
                                         }
-                                        tx.send(ThreadResult::OK(ThreadCommand::PAUSE)).unwrap();
-                                        break;
-                                    },
-                                    _ => {
-                                        // do nothing
+                                        match monitor.guarded(None, Phase::FRAMEEND, || hook.frame_end(&mut interface)) {
+                                            Some(RuntimeStatus::OK) => (),
+                                            Some(_) => {
+                                                println!("Frame end hook errored");
+                                                halt_sim = true;
+                                            },
+                                            None => {
+                                                faulted = true;
+                                                fault = FaultSource::HOOK(ii as u32);
+                                                halt_sim = true;
+                                            }
+                                        }
+                                    }
+                                    if halt_sim {
+                                        // stop the other threads at the next sync point
+                                        csync.request_pause(&*clock);
                                     }
                                 }
-                                // framework activities
                                 // sim time increment
-                                match interface.as_any().downcast_ref::<RSISInterface>() {
-                                    Some(intf) => {
-                                        let mut data = intf.time.lock().unwrap();
-                                        (*data).increment(1);
-                                    }
-                                    None => panic!("Something went terribly wrong. Bad Framework instantiation")
-                                };
-                                //
-                                if srt {
-                                    // sleep to simulate soft real time
-                                    let dur = time_to_next_frame(framestart, frame_width);
-                                    thread::sleep(dur);
+                                frame_clock.advance();
+                                if scale > 0.0 && !halt_sim {
+                                    // sleep to simulate scaled real time
+                                    wait_for_frame(&*clock, run_start + frame_dur * (frame - run_frame) as f64 / scale, &csync.pause_request, &monitor, rsis_interface(&interface));
+                                }
+                                // without a hyperperiod, threads pause as soon as asked
+                                if hyperperiod.is_none() && (halt_sim || csync.pause_request.load(Ordering::Acquire)) {
+                                    // the next step continues from where this thread stopped
+                                    target = frame as f64 * frame_ticks;
+                                    break;
                                 }
-                                cbarrier.wait();
                             }
-                            if halt_sim {
-                                tx.send(ThreadResult::END).unwrap();
+                            if faulted {
+                                // models are stopped when the thread is shut down
+                            } else if halt_sim {
+                                stop_models(&mut u, &monitor);
+                                let _ = tx.send(RunnerEvent::RESULT(pos, ThreadResult::END));
                                 break;
                             } else {
                                 // call pausing function
-                                for obj in &mut u[..] {
-                                    (*obj).model.pause();
+                                for (ii, obj) in u.iter_mut().enumerate() {
+                                    let id = obj.id;
+                                    if monitor.guarded(Some(id), Phase::PAUSE, || obj.model.pause()).is_none() {
+                                        faulted = true;
+                                        fault = FaultSource::MODEL(ii as u32);
+                                    }
                                 }
                             }
-                            tx.send(ThreadResult::OK(ThreadCommand::EXECUTE(value))).unwrap();
+                            if faulted {
+                                // the thread stays alive so that it can be shut down
+                                let _ = tx.send(RunnerEvent::RESULT(pos, ThreadResult::ERR(ThreadCommand::EXECUTE(value), fault)));
+                                continue;
+                            }
+                            let _ = tx.send(RunnerEvent::RESULT(pos, ThreadResult::OK(ThreadCommand::EXECUTE(value))));
                         },
                         Ok(ThreadCommand::PAUSE) => {
                             continue;
                         }
                         Ok(ThreadCommand::SHUTDOWN) => {
-                            tx.send(ThreadResult::END).unwrap();
+                            stop_models(&mut u, &monitor);
+                            let _ = tx.send(RunnerEvent::RESULT(pos, ThreadResult::END));
                             break;
                         },
-                        _ => ()
+                        Err(_) => break // the runner is gone
                     }
                 }
+                (u, hooks)
             }));
             tx_handles.push(txx);
-            rx_handles.push(rx);
-            thread_state.push(SchedulerState::CONFIG);
         }
         
-        let mutex_state = Arc::clone(&self.state);
-        let mut state = SchedulerState::CONFIG;
+        let shared_state = Arc::clone(&self.state);
+        let rsync = Arc::clone(&sync);
+        let rclock = Arc::clone(&self.clock);
+        self.printer = Some(LogPrinter::start(Arc::clone(&self.interface.logs)));
         self.runner = Some(thread::spawn(move|| {
-            let mut thread_state_received = vec![false; threadlen];
-            let mut thread_rcv_num = 0;
+            let mut state = SchedulerState::CONFIG;
+            let set_state = |state : SchedulerState| {
+                shared_state.set(state);
+            };
+            // threads that have responded to the last command
+            let mut received = vec![false; threadlen];
+            let mut received_num = 0;
+            let mut init_failed = false;
+            // threads that have finished, and will not respond again
+            let mut ended = vec![false; threadlen];
+            let mut ended_num = 0;
+            let mut shutting_down = false;
+            let mut shutdown_sent = vec![false; threadlen];
             loop {
-                let stat = mtor_rx.try_recv();
+                // senders are held by the scheduler and the workers, so this
+                // only fails once all of them are gone
+                let event = match event_rx.recv() {
+                    Ok(event) => event,
+                    Err(_) => return
+                };
+                match event {
+                    RunnerEvent::RESULT(pos, ThreadResult::END) | RunnerEvent::EXITED(pos) => {
+                        if !ended[pos] {
+                            ended[pos] = true;
+                            ended_num += 1;
+                        }
+                    },
+                    _ => ()
+                }
+                if let RunnerEvent::EXITED(pos) = event {
+                    println!("Thread {} exited unexpectedly", pos);
+                    if state != SchedulerState::ENDING && state != SchedulerState::TIMEOUT {
+                        state = SchedulerState::ERRORED;
+                        set_state(state);
+                    }
+                }
+                if let RunnerEvent::TIMEOUT(pos) = event {
+                    // the thread cannot be recovered. It is treated as ended
+                    // so that the remaining threads can still be shut down
+                    if !ended[pos] {
+                        ended[pos] = true;
+                        ended_num += 1;
+                    }
+                    rsync.request_pause(&*rclock);
+                    state = SchedulerState::TIMEOUT;
+                    set_state(state);
+                }
                 match state {
                     SchedulerState::CONFIG => {
-                        if stat == Ok(ThreadCommand::INIT) {
+                        if event == RunnerEvent::COMMAND(ThreadCommand::INIT) {
                             send_cmd_to_threads(&mut tx_handles, ThreadCommand::INIT);
                             state = SchedulerState::INITIALIZING;
-                            let mut s = mutex_state.lock().unwrap();
-                            *s = state;
+                            if threadlen == 0 {
+                                state = SchedulerState::INITIALIZED;
+                            }
+                            set_state(state);
                         }
                     },
                     SchedulerState::INITIALIZING => {
-                        // poll state
-                        let mut alldone = true;
-                        for (pos, rx) in rx_handles.iter_mut().enumerate() {
-                            match rx.try_recv() {
-                                Ok(ThreadResult::OK(_)) => {
-                                    thread_state[pos] = SchedulerState::INITIALIZED;
-                                },
-                                Ok(ThreadResult::ERR(_, idx)) => {
-                                    thread_state[pos] = SchedulerState::ERRORED;
-                                    println!("<Thread {}, app {}> errored in init with cmd", pos, idx);
-                                },
-                                _ => (),
-                            }
-                            if thread_state[pos] != SchedulerState::INITIALIZED {
-                                alldone = false;
-                            }
+                        match event {
+                            RunnerEvent::RESULT(pos, ThreadResult::ERR(_, source)) => {
+                                println!("<Thread {}, {}> errored in init with cmd", pos, source);
+                                init_failed = true;
+                            },
+                            RunnerEvent::COMMAND(ThreadCommand::SHUTDOWN) => {
+                                // threads finish initializing before shutting down
+                                shutting_down = true;
+                                state = SchedulerState::ENDING;
+                                set_state(state);
+                            },
+                            _ => ()
                         }
-                        if alldone {
-                            state = SchedulerState::INITIALIZED;
-                            println!("Scenario Initialized");
-                        } else {
-                            state = SchedulerState::ERRORED;
-                            println!("Scenario Initialization Failed");
+                        if let RunnerEvent::RESULT(pos, _) = event {
+                            if !received[pos] {
+                                received[pos] = true;
+                                received_num += 1;
+                            }
                         }
-                        {
-                            let mut s = mutex_state.lock().unwrap();
-                            *s = state;
+                        if received_num == threadlen && !shutting_down {
+                            received_num = 0;
+                            received.iter_mut().for_each(|r| *r = false);
+                            if init_failed {
+                                state = SchedulerState::ERRORED;
+                                println!("Scenario Initialization Failed");
+                            } else {
+                                state = SchedulerState::INITIALIZED;
+                                println!("Scenario Initialized");
+                            }
+                            set_state(state);
                         }
                     },
-                    SchedulerState::INITIALIZED => {
-                        match stat {
-                            Ok(ThreadCommand::EXECUTE(steps)) => {
+                    SchedulerState::INITIALIZED | SchedulerState::PAUSED => {
+                        match event {
+                            RunnerEvent::COMMAND(ThreadCommand::EXECUTE(steps)) => {
+                                rsync.pause_request.store(false, Ordering::Release);
                                 send_cmd_to_threads(&mut tx_handles, ThreadCommand::EXECUTE(steps));
                                 state = SchedulerState::RUNNING;
-                                let mut s = mutex_state.lock().unwrap();
-                                *s = state;
+                                set_state(state);
+                            },
+                            RunnerEvent::COMMAND(ThreadCommand::SHUTDOWN) => {
+                                shutting_down = true;
+                                state = SchedulerState::ENDING;
+                                set_state(state);
                             },
                             _ => ()
                         }
                     },
                     SchedulerState::RUNNING => {
-                        match stat {
-                            Ok(ThreadCommand::PAUSE) => {
-                                send_cmd_to_threads(&mut tx_handles, ThreadCommand::PAUSE);
+                        match event {
+                            RunnerEvent::COMMAND(ThreadCommand::PAUSE) => {
+                                rsync.request_pause(&*rclock);
                             },
-                            Ok(ThreadCommand::SHUTDOWN) => {
-                                send_cmd_to_threads(&mut tx_handles, ThreadCommand::SHUTDOWN);
+                            RunnerEvent::COMMAND(ThreadCommand::SHUTDOWN) => {
+                                // threads finish their current step before shutting down
+                                rsync.request_pause(&*rclock);
+                                shutting_down = true;
                                 state = SchedulerState::ENDING;
-                                let mut s = mutex_state.lock().unwrap();
-                                *s = state;
-                                continue;
+                                set_state(state);
                             },
-                            _ => ()
-                        }
-                        // poll state
-                        for (pos, rx) in rx_handles.iter_mut().enumerate() {
-                            match rx.try_recv() {
-                                Ok(ThreadResult::OK(_)) => {
-                                    if !thread_state_received[pos] {
-                                        thread_state_received[pos] = true;
-                                        thread_rcv_num += 1;
-                                    }
-                                    if thread_rcv_num == threadlen {
-                                        thread_rcv_num = 0;
-                                        for state in thread_state_received.iter_mut() {
-                                            *state = false;
-                                        }
-
-                                        state = SchedulerState::PAUSED;
-                                        let mut s = mutex_state.lock().unwrap();
-                                        *s = state;
-                                    }
-                                },
-                                Ok(ThreadResult::ERR(_, _)) => {
-                                    println!("Thread {} reported an error", pos);
-                                    state = SchedulerState::ERRORED;
-                                    let mut s = mutex_state.lock().unwrap();
-                                    *s = state;
-                                },
-                                Ok(ThreadResult::END) => {
-                                    state = SchedulerState::ENDED;
-                                    let mut s = mutex_state.lock().unwrap();
-                                    *s = state;
-                                },
-                                Err(TryRecvError::Disconnected) => {
-                                    println!("Channel is disconnected");
-                                    state = SchedulerState::ERRORED;
-                                    let mut s = mutex_state.lock().unwrap();
-                                    *s = state;
-                                },
-                                Err(TryRecvError::Empty) => () // nothing received yet
-                            }
-                        }
-                    },
-                    SchedulerState::PAUSED => {
-                        match stat {
-                            Ok(ThreadCommand::EXECUTE(steps)) => {
-                                send_cmd_to_threads(&mut tx_handles, ThreadCommand::EXECUTE(steps));
-                                state = SchedulerState::RUNNING;
-                                let mut s = mutex_state.lock().unwrap();
-                                *s = state;
+                            RunnerEvent::RESULT(pos, ThreadResult::OK(_)) => {
+                                if !received[pos] {
+                                    received[pos] = true;
+                                    received_num += 1;
+                                }
+                                if received_num == threadlen {
+                                    received_num = 0;
+                                    received.iter_mut().for_each(|r| *r = false);
+                                    // the threads are idle, and all have run to the slowest of them
+                                    let horizon = frame_clocks.iter().fold(f64::INFINITY, |acc, clock| f64::min(acc, clock.value()));
+                                    frame_clocks.iter().for_each(|clock| clock.set_horizon(horizon));
+                                    state = SchedulerState::PAUSED;
+                                    set_state(state);
+                                }
                             },
-                            Ok(ThreadCommand::SHUTDOWN) => {
-                                send_cmd_to_threads(&mut tx_handles, ThreadCommand::SHUTDOWN);
-                                state = SchedulerState::ENDING;
-                                let mut s = mutex_state.lock().unwrap();
-                                *s = state;
+                            RunnerEvent::RESULT(pos, ThreadResult::ERR(_, source)) => {
+                                println!("Thread {} reported an error in {}", pos, source);
+                                state = SchedulerState::ERRORED;
+                                set_state(state);
+                            },
+                            RunnerEvent::RESULT(_, ThreadResult::END) => {
+                                state = SchedulerState::ENDED;
+                                set_state(state);
                             },
                             _ => ()
                         }
                     },
-                    SchedulerState::ENDING => {
-                        // poll waiting for threads to report finished
-                        let mut end_received = vec![false; threadlen];
-                        let mut end_num = 0;
-                        loop {
-                            for (pos, rx) in rx_handles.iter_mut().enumerate() {
-                                match rx.try_recv() {
-                                    Ok(ThreadResult::END) => {
-                                        if !end_received[pos] {
-                                            end_received[pos] = true;
-                                            end_num += 1;
-                                        }
-                                        if end_num == threadlen {
-                                            state = SchedulerState::ENDED;
-                                            let mut s = mutex_state.lock().unwrap();
-                                            *s = state;
-
-                                            println!("Simulation completed. {} threads exited successfully.", threadlen);
-                                            return;
-                                        }
-                                    },
-                                    _ => ()
-                                }
-                            }
-                            thread::sleep(time::Duration::from_millis(20)); // sleep to prevent hogging the cpu
+                    SchedulerState::ENDING => (), // wait for threads to report finished
+                    SchedulerState::ENDED | SchedulerState::ERRORED | SchedulerState::TIMEOUT => {
+                        // release any threads that are still waiting for commands
+                        if event == RunnerEvent::COMMAND(ThreadCommand::SHUTDOWN) {
+                            shutting_down = true;
                         }
-                    },
-                    _ => ()
+                    }
+                }
+                if shutting_down {
+                    if ended_num == threadlen {
+                        if state == SchedulerState::ENDING {
+                            state = SchedulerState::ENDED;
+                            set_state(state);
+                            println!("Simulation completed. {} threads exited successfully.", threadlen);
+                        }
+                        return;
+                    }
+                    shutdown_next_thread(&mut tx_handles, &ended, &mut shutdown_sent);
                 }
-                thread::sleep(time::Duration::from_millis(20)); // sleep to prevent hogging the cpu
             }
         }));
-        return (mtor_tx, rtom_rx);
+        return event_tx;
     }
 }
 
 impl Scheduler for NRTScheduler {
     fn clear_threads(&mut self) -> () {
+        let ids : Vec<ModelId> = self.threads.iter().flat_map(|ts| ts.models.iter().map(|obj| obj.id)).collect();
+        // dropping the models closes the channel ends they hold
         self.threads.clear();
+        self.controls.clear();
+        for id in ids {
+            self.interface.release_model(id);
+        }
     }
     fn add_thread(&mut self, freq : f64) -> (){
         self.threads.push(ThreadState {
             frequency: freq,
             models: Vec::new(),
+            hooks: Vec::new(),
         })
     }
-    fn add_model(&mut self, model : Box<Box<dyn BaseModel + Send>>, thread: usize, divisor: i64, offset: i64) -> *mut c_void {
-        if thread > self.threads.len() {
-            return 0 as *mut c_void;
+    fn check_model_at(&self, thread: usize, index: Option<usize>, divisor: i64, offset: i64) -> Result<(), ScheduleError> {
+        if self.get_state() != SchedulerState::CONFIG {
+            return Err(ScheduleError::BADSTATE);
+        }
+        if thread >= self.threads.len() {
+            return Err(ScheduleError::BADTHREAD);
+        }
+        if index.map_or(false, |index| index > self.threads[thread].models.len()) {
+            return Err(ScheduleError::BADINDEX);
         }
+        validate_rate(divisor, offset)
+    }
+    fn add_model(&mut self, model : Box<Box<dyn BaseModel + Send>>, thread: usize, divisor: i64, offset: i64) -> Result<ModelId, ScheduleError> {
+        self.check_model_at(thread, None, divisor, offset)?;
+        let index = self.threads[thread].models.len();
+        self.insert_model_at(model, thread, index, divisor, offset)
+    }
+    fn insert_model_at(&mut self, model : Box<Box<dyn BaseModel + Send>>, thread: usize, index: usize, divisor: i64, offset: i64) -> Result<ModelId, ScheduleError> {
+        self.check_model_at(thread, Some(index), divisor, offset)?;
+        let control = Arc::new(ModelControl::new(divisor, offset));
         let obj = ScheduledObject {
-            model: *model,
+            id: self.next_id,
+            model: model,
             divisor: divisor,
             offset: offset,
-            counter: offset % divisor,
+            counter: offset,
+            enabled: true,
+            control: Arc::clone(&control),
         };
-        self.threads[thread].models.push(obj);
-        return &self.threads[thread].models.last().unwrap().model as *const Box<dyn BaseModel + Send> as *mut c_void;
+        self.controls.insert(obj.id, ScheduledHandle {
+            thread: thread,
+            control: control,
+        });
+        self.next_id += 1;
+        self.threads[thread].models.insert(index, obj);
+        Ok(self.threads[thread].models[index].id)
+    }
+    fn move_model(&mut self, id : ModelId, thread : usize, index : usize) -> Result<(), ScheduleError> {
+        if self.get_state() != SchedulerState::CONFIG {
+            return Err(ScheduleError::BADSTATE);
+        }
+        if thread >= self.threads.len() {
+            return Err(ScheduleError::BADTHREAD);
+        }
+        let (src_thread, src_index) = self.find_model(id).ok_or(ScheduleError::BADID)?;
+        // the destination index is interpreted after the model has been taken out
+        let mut len = self.threads[thread].models.len();
+        if src_thread == thread {
+            len -= 1;
+        }
+        if index > len {
+            return Err(ScheduleError::BADINDEX);
+        }
+        let obj = self.threads[src_thread].models.remove(src_index);
+        self.threads[thread].models.insert(index, obj);
+        if let Some(handle) = self.controls.get_mut(&id) {
+            handle.thread = thread;
+        }
+        Ok(())
     }
     fn remove_model(&mut self, thread : usize, id : usize) -> i32 {
         if thread >= self.threads.len() {
@@ -388,38 +865,131 @@ impl Scheduler for NRTScheduler {
         if id >= self.threads[thread].models.len() {
             return 2;
         }
-        self.threads[thread].models.remove(id);
+        // dropping the model closes the channel ends it holds
+        let obj_id = self.threads[thread].models.remove(id).id;
+        self.controls.remove(&obj_id);
+        self.interface.release_model(obj_id);
         0
     }
+    fn remove_model_by_id(&mut self, id : ModelId) -> Result<(), ScheduleError> {
+        if self.get_state() != SchedulerState::CONFIG {
+            return Err(ScheduleError::BADSTATE);
+        }
+        let (thread, index) = self.find_model(id).ok_or(ScheduleError::BADID)?;
+        // dropping the model closes the channel ends it holds
+        self.threads[thread].models.remove(index);
+        self.controls.remove(&id);
+        self.interface.release_model(id);
+        Ok(())
+    }
+    fn get_model_ptr(&self, id : ModelId) -> *mut c_void {
+        match self.find_model(id) {
+            Some((thread, index)) => {
+                let model : &Box<dyn BaseModel + Send> = &self.threads[thread].models[index].model;
+                model as *const Box<dyn BaseModel + Send> as *mut c_void
+            },
+            None => 0 as *mut c_void
+        }
+    }
+    fn set_model_enabled(&mut self, id : ModelId, enabled : bool) -> Result<(), ScheduleError> {
+        let handle = self.controls.get(&id).ok_or(ScheduleError::BADID)?;
+        handle.control.enabled.store(enabled, Ordering::Release);
+        Ok(())
+    }
+    fn get_model_enabled(&self, id : ModelId) -> Option<bool> {
+        self.controls.get(&id).map(|handle| handle.control.enabled.load(Ordering::Acquire))
+    }
+    fn set_model_rate(&mut self, id : ModelId, divisor : i64, offset : i64) -> Result<(), ScheduleError> {
+        match self.get_state() {
+            SchedulerState::CONFIG | SchedulerState::INITIALIZED | SchedulerState::PAUSED => (),
+            _ => return Err(ScheduleError::BADSTATE)
+        }
+        let handle = self.controls.get(&id).ok_or(ScheduleError::BADID)?;
+        // the same rates are accepted as when the model was scheduled
+        validate_rate(divisor, offset)?;
+        handle.control.divisor.store(divisor, Ordering::Release);
+        handle.control.offset.store(offset, Ordering::Release);
+        handle.control.rate_changed.store(true, Ordering::Release);
+        Ok(())
+    }
+    fn add_frame_hook(&mut self, thread : usize, hook : Box<dyn FrameHook + Send>) -> Result<(), ScheduleError> {
+        if self.get_state() != SchedulerState::CONFIG {
+            return Err(ScheduleError::BADSTATE);
+        }
+        if thread >= self.threads.len() {
+            return Err(ScheduleError::BADTHREAD);
+        }
+        self.threads[thread].hooks.push(hook);
+        Ok(())
+    }
+    fn set_clock(&mut self, clock : Box<dyn ClockSource>) -> Result<(), ScheduleError> {
+        if self.get_state() != SchedulerState::CONFIG {
+            return Err(ScheduleError::BADSTATE);
+        }
+        self.clock = Arc::from(clock);
+        // a clock only paces frames while pacing is on
+        if self.realtime_scale.get() == 0.0 {
+            self.realtime_scale.set(1.0);
+            println!("Frames are now paced in real time by the configured clock");
+        }
+        Ok(())
+    }
     fn get_num_threads(&self) -> i32 {
         self.threads.len() as i32
     }
-    fn config(&mut self, key : &[u8], _value : &[u8]) -> Option<i32> {
-        let mut key_s : String = String::from("");
-        match rmps::decode::from_read(key) {
-            Ok(val) => {
-                key_s = val;
+    fn config(&mut self, key : &str, value : &[u8]) -> Result<(), ConfigError> {
+        let entry = ConfigKey::find(&NRT_CONFIG, key)?;
+        let val = entry.parse(self.get_state(), value)?;
+        // pacing is picked up by the worker threads at the next step
+        match (entry.name, val) {
+            ("srt", ConfigValue::BOOL(on)) => {
+                self.realtime_scale.set(if on { 1.0 } else { 0.0 });
             },
-            Err(_) => return Some(0)
-        }
-        match key_s.as_str() {
-            "srt" => {
-                self.soft_real_time = true;
-                println!("Soft real-time enabled");
+            ("rtscale", ConfigValue::FLOAT(scale)) => {
+                if !self.realtime_scale.set(scale) {
+                    return Err(ConfigError::BADVALUE);
+                }
             },
-            _ => {
-                println!("Invalid config key");
-                return Some(1);
-            }
+            ("watchdog", ConfigValue::FLOAT(deadline)) => {
+                self.watchdog_deadline = deadline;
+            },
+            ("loglevel", ConfigValue::INT(level)) => {
+                if !(0..=3).contains(&level) {
+                    return Err(ConfigError::BADVALUE);
+                }
+                self.interface.logs.set_level(level as u32);
+            },
+            ("lograte", ConfigValue::FLOAT(rate)) => {
+                if !(rate >= 0.0) || rate.is_infinite() {
+                    return Err(ConfigError::BADVALUE);
+                }
+                self.interface.logs.set_rate(rate);
+            },
+            _ => return Err(ConfigError::BADKEY)
+        }
+        println!("Scheduler config {} set to {}", key, val);
+        Ok(())
+    }
+    fn get_config(&self, key : &str) -> Result<ConfigValue, ConfigError> {
+        match key {
+            "srt" => Ok(ConfigValue::BOOL(self.realtime_scale.get() == 1.0)),
+            "rtscale" => Ok(ConfigValue::FLOAT(self.realtime_scale.get())),
+            "watchdog" => Ok(ConfigValue::FLOAT(self.watchdog_deadline)),
+            "loglevel" => Ok(ConfigValue::INT(self.interface.logs.level() as i64)),
+            "lograte" => Ok(ConfigValue::FLOAT(self.interface.logs.rate())),
+            _ => Err(ConfigError::BADKEY)
         }
-        return None;
+    }
+    fn list_config(&self) -> Vec<ConfigEntry> {
+        NRT_CONFIG.iter().filter_map(|key| {
+            self.get_config(key.name).ok().map(|val| ConfigEntry::new(key, val))
+        }).collect()
     }
     fn init(&mut self) -> i32 {
-        let (tx, rx) = self.start_runner();
-        match tx.send(ThreadCommand::INIT) {
+        let tx = self.start_runner();
+        match tx.send(RunnerEvent::COMMAND(ThreadCommand::INIT)) {
             Ok(_) => {
                 self.runner_tx = Some(tx);
-                self.runner_rx = Some(rx);
                 return 0;
             },
             _ => {
@@ -430,7 +1000,7 @@ impl Scheduler for NRTScheduler {
     fn step(&mut self, steps: u64) -> i32 {
         match &self.runner_tx {
             Some(tx) => {
-                match tx.send(ThreadCommand::EXECUTE(steps)) {
+                match tx.send(RunnerEvent::COMMAND(ThreadCommand::EXECUTE(steps))) {
                     Ok(_) => {
                         return 0;
                     },
@@ -444,10 +1014,23 @@ impl Scheduler for NRTScheduler {
             }
         }
     }
+    fn step_blocking(&mut self, steps: u64, timeout : Option<time::Duration>) -> Option<SchedulerState> {
+        let (current, version) = self.state.get();
+        if current != SchedulerState::INITIALIZED && current != SchedulerState::PAUSED {
+            return Some(current);
+        }
+        if self.step(steps) != 0 {
+            return Some(current);
+        }
+        // the runner moves through RUNNING, so any later PAUSED belongs to this step
+        self.state.wait_until(timeout, |state, ver| {
+            ver > version && (state == SchedulerState::PAUSED || state.is_terminal())
+        })
+    }
     fn pause(&mut self) -> i32 {
         match &self.runner_tx {
             Some(tx) => {
-                match tx.send(ThreadCommand::PAUSE) {
+                match tx.send(RunnerEvent::COMMAND(ThreadCommand::PAUSE)) {
                     Ok(_) => {
                         return 0;
                     },
@@ -464,7 +1047,7 @@ impl Scheduler for NRTScheduler {
     fn end(&mut self) -> i32 {
         match &self.runner_tx {
             Some(tx) => {
-                match tx.send(ThreadCommand::SHUTDOWN) {
+                match tx.send(RunnerEvent::COMMAND(ThreadCommand::SHUTDOWN)) {
                     Ok(_) => {
                         return 0;
                     },
@@ -478,29 +1061,281 @@ impl Scheduler for NRTScheduler {
             }
         }
     }
-    fn get_state(&self) -> SchedulerState {
-        match self.state.lock() {
-            Ok(status) => {
-                *status
-            },
-            _ => {
-                SchedulerState::ERRORED
+    fn shutdown(&mut self, timeout : Option<time::Duration>) -> i32 {
+        let deadline = timeout.map(|t| time::Instant::now() + t);
+        if let Some(tx) = self.runner_tx.take() {
+            // the runner returns once every worker thread has ended
+            let _ = tx.send(RunnerEvent::COMMAND(ThreadCommand::SHUTDOWN));
+        }
+        let mut failed = 0; // threads that did not hand back their models
+        if let Some(runner) = self.runner.take() {
+            if !wait_for_exit(&runner, deadline) {
+                println!("Runner thread did not exit within the shutdown timeout");
+                failed += 1;
+            } else if runner.join().is_err() {
+                println!("Runner thread panicked");
             }
         }
+        // return models to their threads
+        for (pos, handle) in self.handles.drain(..).enumerate() {
+            if !wait_for_exit(&handle, deadline) {
+                println!("Thread {} did not exit within the shutdown timeout", pos);
+                failed += 1;
+                continue;
+            }
+            match handle.join() {
+                Ok((models, hooks)) => {
+                    self.threads[pos].models = models;
+                    self.threads[pos].hooks = hooks;
+                },
+                Err(_) => {
+                    println!("Thread {} panicked. Its models have been lost", pos);
+                    failed += 1;
+                }
+            }
+        }
+        if let Some(watchdog) = self.watchdog.take() {
+            watchdog.stop();
+        }
+        // prints messages logged while stopping
+        self.printer.take();
+        failed
+    }
+    fn reset(&mut self) -> i32 {
+        let mut status = 0;
+        if self.shutdown(Some(time::Duration::from_secs_f64(SHUTDOWN_TIMEOUT))) != 0 {
+            status = 1;
+        }
+        for ts in self.threads.iter_mut() {
+            for obj in ts.models.iter_mut() {
+                obj.counter = obj.offset;
+            }
+        }
+        // models lost to a panic or a hung thread can no longer be referenced
+        let threads = &self.threads;
+        self.controls.retain(|id, handle| {
+            threads.get(handle.thread).is_some_and(|ts| ts.models.iter().any(|obj| obj.id == *id))
+        });
+        // sim time restarts at zero, as the next init gives every worker
+        // thread a new frame clock
+        self.faults.clear();
+        // models request their channels again when initialized
+        self.interface.clear();
+        self.state.set(SchedulerState::CONFIG);
+        status
+    }
+    fn get_state(&self) -> SchedulerState {
+        self.state.get().0
+    }
+    fn get_faults(&self) -> Vec<ModelFault> {
+        self.faults.get()
+    }
+    fn list_channels(&self) -> Vec<ChannelInfo> {
+        self.interface.list_channels()
+    }
+    fn set_channel_latency(&mut self, key : ChannelKey, latency : f64) -> Result<(), ChannelError> {
+        self.interface.set_latency(key, latency)
+    }
+    fn start_recording(&mut self, path : &str) -> std::io::Result<()> {
+        self.interface.start_recording(path)
+    }
+    fn stop_recording(&mut self) -> std::io::Result<()> {
+        self.interface.stop_recording()
+    }
+    fn wait_for_state(&self, state : SchedulerState, timeout : Option<time::Duration>) -> Option<SchedulerState> {
+        self.state.wait_until(timeout, |current, _| current == state || current.is_terminal())
     }
 }
 
 impl NRTScheduler {
+    // Returns the (thread, index) location of a scheduled model
+    fn find_model(&self, id : ModelId) -> Option<(usize, usize)> {
+        for (thread, ts) in self.threads.iter().enumerate() {
+            if let Some(index) = ts.models.iter().position(|obj| obj.id == id) {
+                return Some((thread, index));
+            }
+        }
+        None
+    }
+
     pub fn new() -> NRTScheduler {
         NRTScheduler {
             threads: Vec::<ThreadState>::new(),
             handles: Vec::new(),
-            state  : Arc::new(Mutex::new(SchedulerState::CONFIG)),
+            state  : Arc::new(StateSignal::new(SchedulerState::CONFIG)),
             runner : None,
             runner_tx : None,
-            runner_rx : None,
             interface : RSISInterface::new(),
-            soft_real_time : false,
+            next_id : 1,
+            controls : HashMap::new(),
+            faults : Arc::new(FaultLog::new()),
+            watchdog : None,
+            printer : None,
+            realtime_scale : Arc::new(RealTimeScale::new()),
+            watchdog_deadline : 0.0,
+            clock : Arc::new(MonotonicClock::new()),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsisappinterface::{BufferStruct, ConfigStatus, SizeCallback};
+    use std::sync::atomic::AtomicI64;
+
+    struct Counter {
+        steps : Arc<AtomicI64>,
+        tick : Arc<AtomicI64>, // sim tick of the last step
+    }
+
+    impl BaseModel for Counter {
+        fn config(&mut self) -> ConfigStatus {
+            ConfigStatus::OK
+        }
+        fn init(&mut self, _interface : &mut Box<dyn Framework>) -> RuntimeStatus {
+            RuntimeStatus::OK
+        }
+        fn step(&mut self, interface : &mut Box<dyn Framework>) -> RuntimeStatus {
+            self.steps.fetch_add(1, Ordering::SeqCst);
+            self.tick.store(interface.get_simtick(), Ordering::SeqCst);
+            RuntimeStatus::OK
+        }
+        fn pause(&mut self) -> RuntimeStatus {
+            RuntimeStatus::OK
+        }
+        fn stop(&mut self) -> RuntimeStatus {
+            RuntimeStatus::OK
+        }
+        fn msg_get(&self, _id : BufferStruct, _cb : SizeCallback) -> u32 {
+            1
+        }
+        fn msg_set(&mut self, _id : BufferStruct, _data : BufferStruct) -> u32 {
+            1
+        }
+        fn get_ptr(&self, _id : BufferStruct) -> *const u8 {
+            std::ptr::null()
+        }
+    }
+
+    // Returns the steps taken and the tick of the last one
+    fn add_counter(scheduler : &mut NRTScheduler, thread : usize) -> (Arc<AtomicI64>, Arc<AtomicI64>) {
+        let steps = Arc::new(AtomicI64::new(0));
+        let tick = Arc::new(AtomicI64::new(-1));
+        let model : Box<Box<dyn BaseModel + Send>> = Box::new(Box::new(Counter {
+            steps : Arc::clone(&steps),
+            tick : Arc::clone(&tick),
+        }));
+        scheduler.add_model(model, thread, 1, 0).unwrap();
+        (steps, tick)
+    }
+
+    const TIMEOUT : Option<time::Duration> = Some(time::Duration::from_secs(5));
+
+    #[test]
+    fn single_steps_meet_at_every_hyperperiod() {
+        let mut scheduler = NRTScheduler::new();
+        scheduler.add_thread(100.0);
+        scheduler.add_thread(10.0);
+        let (fast, _) = add_counter(&mut scheduler, 0);
+        let (slow, _) = add_counter(&mut scheduler, 1);
+        scheduler.init();
+        assert!(scheduler.wait_for_state(SchedulerState::INITIALIZED, TIMEOUT) == Some(SchedulerState::INITIALIZED));
+        // the slow thread runs a frame ahead of the fast one, and must still
+        // meet it at each boundary it has already passed
+        for _ in 0..25 {
+            assert!(scheduler.step_blocking(1, TIMEOUT) == Some(SchedulerState::PAUSED));
+        }
+        assert_eq!(fast.load(Ordering::SeqCst), 25);
+        assert_eq!(slow.load(Ordering::SeqCst), 3);
+        assert_eq!(scheduler.shutdown(TIMEOUT), 0);
+    }
+
+    #[test]
+    fn ticks_compare_across_threads() {
+        let mut scheduler = NRTScheduler::new();
+        scheduler.add_thread(100.0);
+        scheduler.add_thread(10.0);
+        let (_, fast) = add_counter(&mut scheduler, 0);
+        let (_, slow) = add_counter(&mut scheduler, 1);
+        scheduler.init();
+        assert!(scheduler.wait_for_state(SchedulerState::INITIALIZED, TIMEOUT) == Some(SchedulerState::INITIALIZED));
+        assert!(scheduler.step_blocking(25, TIMEOUT) == Some(SchedulerState::PAUSED));
+        // both count ticks of the 100 Hz thread
+        assert_eq!(fast.load(Ordering::SeqCst), 24);
+        assert_eq!(slow.load(Ordering::SeqCst), 20);
+        assert_eq!(scheduler.shutdown(TIMEOUT), 0);
+    }
+
+    #[test]
+    fn reset_restarts_sim_time() {
+        let mut scheduler = NRTScheduler::new();
+        scheduler.add_thread(10.0);
+        let (steps, tick) = add_counter(&mut scheduler, 0);
+        for _ in 0..2 {
+            scheduler.init();
+            assert!(scheduler.wait_for_state(SchedulerState::INITIALIZED, TIMEOUT) == Some(SchedulerState::INITIALIZED));
+            assert!(scheduler.step_blocking(1, TIMEOUT) == Some(SchedulerState::PAUSED));
+            assert_eq!(tick.load(Ordering::SeqCst), 0);
+            assert_eq!(scheduler.reset(), 0);
+        }
+        assert_eq!(steps.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn cleared_models_cannot_be_referenced() {
+        let mut scheduler = NRTScheduler::new();
+        scheduler.add_thread(10.0);
+        add_counter(&mut scheduler, 0);
+        let id = scheduler.threads[0].models[0].id;
+        assert_eq!(scheduler.get_model_enabled(id), Some(true));
+        scheduler.clear_threads();
+        assert_eq!(scheduler.get_model_enabled(id), None);
+        assert!(scheduler.set_model_enabled(id, false).is_err());
+        assert!(scheduler.set_model_rate(id, 1, 0).is_err());
+    }
+
+    #[test]
+    fn rates_are_checked_the_same_when_changed() {
+        let mut scheduler = NRTScheduler::new();
+        scheduler.add_thread(2.5);
+        add_counter(&mut scheduler, 0);
+        let id = scheduler.threads[0].models[0].id;
+        assert!(scheduler.set_model_rate(id, 1, 0).is_ok());
+        assert!(scheduler.set_model_rate(id, 3, 2).is_ok());
+        assert!(scheduler.set_model_rate(id, 0, 0) == Err(ScheduleError::BADDIVISOR));
+        assert!(scheduler.set_model_rate(id, 2, 2) == Err(ScheduleError::BADOFFSET));
+    }
+
+    struct FailingStart;
+
+    impl FrameHook for FailingStart {
+        fn frame_start(&mut self, _interface : &mut Box<dyn Framework>) -> RuntimeStatus {
+            RuntimeStatus::ERROR
+        }
+    }
+
+    #[test]
+    fn models_skip_frames_whose_start_hooks_fail() {
+        let mut scheduler = NRTScheduler::new();
+        scheduler.add_thread(10.0);
+        let (steps, _) = add_counter(&mut scheduler, 0);
+        scheduler.add_frame_hook(0, Box::new(FailingStart)).unwrap();
+        scheduler.init();
+        assert!(scheduler.wait_for_state(SchedulerState::INITIALIZED, TIMEOUT) == Some(SchedulerState::INITIALIZED));
+        // a failed hook ends the simulation like a failed model
+        assert!(scheduler.step_blocking(5, TIMEOUT) == Some(SchedulerState::ENDED));
+        assert_eq!(steps.load(Ordering::SeqCst), 0);
+        assert_eq!(scheduler.shutdown(TIMEOUT), 0);
+    }
+
+    #[test]
+    fn setting_a_clock_turns_on_pacing() {
+        let mut scheduler = NRTScheduler::new();
+        scheduler.set_clock(Box::new(MonotonicClock::new())).unwrap();
+        assert_eq!(scheduler.realtime_scale.get(), 1.0);
+        scheduler.realtime_scale.set(0.5);
+        scheduler.set_clock(Box::new(MonotonicClock::new())).unwrap();
+        assert_eq!(scheduler.realtime_scale.get(), 0.5);
+    }
+}
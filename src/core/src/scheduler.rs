@@ -2,9 +2,19 @@
 extern crate rsisappinterface;
 
 use rsisappinterface::BaseModel;
+use crate::hooks::FrameHook;
+use crate::clock::ClockSource;
+use crate::channel::{ChannelError, ChannelInfo, ChannelKey};
+use crate::config::{ConfigEntry, ConfigError, ConfigValue};
+use crate::status;
+use std::any::Any;
 use std::ffi::c_void;
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::time::{Duration, Instant};
 
-#[derive(Copy,Clone,PartialEq)]
+#[derive(Copy,Clone,PartialEq,Debug)]
 pub enum SchedulerState {
     CONFIG       = 0,
     INITIALIZING = 1,
@@ -14,28 +24,271 @@ pub enum SchedulerState {
     ENDING       = 5,
     ENDED        = 6,
     ERRORED      = 7,
+    TIMEOUT      = 8, // a model call exceeded the watchdog deadline
+}
+
+impl SchedulerState {
+    pub fn from_i32(value : i32) -> Option<SchedulerState> {
+        match value {
+            0 => Some(SchedulerState::CONFIG),
+            1 => Some(SchedulerState::INITIALIZING),
+            2 => Some(SchedulerState::INITIALIZED),
+            3 => Some(SchedulerState::RUNNING),
+            4 => Some(SchedulerState::PAUSED),
+            5 => Some(SchedulerState::ENDING),
+            6 => Some(SchedulerState::ENDED),
+            7 => Some(SchedulerState::ERRORED),
+            8 => Some(SchedulerState::TIMEOUT),
+            _ => None
+        }
+    }
+
+    // States that the scheduler cannot leave on its own
+    pub fn is_terminal(&self) -> bool {
+        *self == SchedulerState::ENDED || *self == SchedulerState::ERRORED || *self == SchedulerState::TIMEOUT
+    }
+}
+
+// Scheduler state that other threads can block on. Every change bumps the
+// version, which lets a caller tell a new state apart from an identical
+// earlier one, e.g. PAUSED before and after a step
+pub struct StateSignal {
+    state   : Mutex<(SchedulerState, u64)>,
+    changed : Condvar,
+}
+
+impl StateSignal {
+    pub fn new(state : SchedulerState) -> StateSignal {
+        StateSignal {
+            state   : Mutex::new((state, 0)),
+            changed : Condvar::new(),
+        }
+    }
+
+    pub fn get(&self) -> (SchedulerState, u64) {
+        match self.state.lock() {
+            Ok(status) => *status,
+            _ => (SchedulerState::ERRORED, 0)
+        }
+    }
+
+    pub fn set(&self, state : SchedulerState) {
+        let mut status = self.state.lock().unwrap();
+        status.0 = state;
+        status.1 += 1;
+        self.changed.notify_all();
+    }
+
+    // Blocks until `done` accepts the current state and version. Returns the
+    // accepted state, or None if the timeout expired first
+    pub fn wait_until<F>(&self, timeout : Option<Duration>, done : F) -> Option<SchedulerState>
+        where F : Fn(SchedulerState, u64) -> bool
+    {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut status = self.state.lock().unwrap();
+        while !done(status.0, status.1) {
+            match deadline {
+                Some(end) => {
+                    let now = Instant::now();
+                    if now >= end {
+                        return None;
+                    }
+                    status = self.changed.wait_timeout(status, end - now).unwrap().0;
+                },
+                None => {
+                    status = self.changed.wait(status).unwrap();
+                }
+            }
+        }
+        Some(status.0)
+    }
+}
+
+// Identifier handed out when a model is scheduled. Unlike the position of
+// a model within a thread, the id does not change when other models are
+// inserted, moved, or removed.
+pub type ModelId = u64;
+
+// Reasons a model could not be scheduled or rescheduled. The values are
+// taken from status::SCHEDULE
+#[repr(u32)]
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum ScheduleError {
+    BADTHREAD  = status::SCHEDULE.start,
+    BADDIVISOR = status::SCHEDULE.start + 1,
+    BADOFFSET  = status::SCHEDULE.start + 2,
+    NULLMODEL  = status::SCHEDULE.start + 3,
+    BADID      = status::SCHEDULE.start + 4,
+    BADINDEX   = status::SCHEDULE.start + 5,
+    BADSTATE   = status::SCHEDULE.start + 6,
+}
+
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum FaultKind {
+    PANIC,   // the scheduler moves to ERRORED
+    TIMEOUT, // the scheduler moves to TIMEOUT
+}
+
+// A model or frame hook that failed inside a worker thread. The thread
+// stops stepping its models
+#[derive(Clone)]
+pub struct ModelFault {
+    pub kind    : FaultKind,
+    pub thread  : usize,
+    pub model   : Option<ModelId>, // None for a frame hook, or a wait of the scheduler
+    pub phase   : &'static str,    // model call that failed
+    pub message : String,
+}
+
+impl fmt::Display for ModelFault {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        let what = match self.kind {
+            FaultKind::PANIC => "panicked",
+            FaultKind::TIMEOUT => "timed out"
+        };
+        match self.model {
+            Some(id) => write!(f, "<Thread {}, model {}> {} in {}: {}", self.thread, id, what, self.phase, self.message),
+            None => {
+                let source = match self.phase {
+                    "frame_start" | "frame_end" => "frame hook",
+                    _ => "scheduler"
+                };
+                write!(f, "<Thread {}, {}> {} in {}: {}", self.thread, source, what, self.phase, self.message)
+            }
+        }
+    }
+}
+
+// Extracts the message passed to panic!, if it was a string
+pub fn panic_message(payload : &Box<dyn Any + Send>) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        String::from(*msg)
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        String::from("unknown panic")
+    }
+}
+
+// Faults shared between the worker threads and the scheduler
+pub struct FaultLog {
+    faults : Mutex<Vec<ModelFault>>,
+}
+
+impl FaultLog {
+    pub fn new() -> FaultLog {
+        FaultLog {
+            faults : Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn record(&self, fault : ModelFault) {
+        println!("{}", fault);
+        if let Ok(mut faults) = self.faults.lock() {
+            faults.push(fault);
+        }
+    }
+
+    pub fn get(&self) -> Vec<ModelFault> {
+        match self.faults.lock() {
+            Ok(faults) => faults.clone(),
+            _ => Vec::new()
+        }
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut faults) = self.faults.lock() {
+            faults.clear();
+        }
+    }
 }
 
 pub trait Scheduler {
     fn clear_threads(&mut self) -> ();
     fn add_thread(&mut self, freq : f64) -> ();
-    fn add_model(&mut self, model: Box<Box<dyn BaseModel + Send>>, thread: usize, divisor: i64, offset: i64) -> *mut c_void;
+    // Checks that a model could be scheduled at `index`, or at the end of the
+    // thread if None, without scheduling it
+    fn check_model_at(&self, thread: usize, index: Option<usize>, divisor: i64, offset: i64) -> Result<(), ScheduleError>;
+    fn add_model(&mut self, model: Box<Box<dyn BaseModel + Send>>, thread: usize, divisor: i64, offset: i64) -> Result<ModelId, ScheduleError>;
+    fn insert_model_at(&mut self, model: Box<Box<dyn BaseModel + Send>>, thread: usize, index: usize, divisor: i64, offset: i64) -> Result<ModelId, ScheduleError>;
+    fn move_model(&mut self, id : ModelId, thread : usize, index : usize) -> Result<(), ScheduleError>;
     fn remove_model(&mut self, thread : usize, id : usize) -> i32;
+    fn remove_model_by_id(&mut self, id : ModelId) -> Result<(), ScheduleError>;
+    fn get_model_ptr(&self, id : ModelId) -> *mut c_void;
+    fn set_model_enabled(&mut self, id : ModelId, enabled : bool) -> Result<(), ScheduleError>;
+    fn get_model_enabled(&self, id : ModelId) -> Option<bool>;
+    fn set_model_rate(&mut self, id : ModelId, divisor : i64, offset : i64) -> Result<(), ScheduleError>;
+    fn add_frame_hook(&mut self, thread : usize, hook : Box<dyn FrameHook + Send>) -> Result<(), ScheduleError>;
+    // Also turns on pacing at a scale of 1 if it was off
+    fn set_clock(&mut self, clock : Box<dyn ClockSource>) -> Result<(), ScheduleError>;
     fn get_num_threads(&self) -> i32;
 
-    fn config(&mut self, key : &[u8], value : &[u8]) -> Option<i32>;
+    fn config(&mut self, key : &str, value : &[u8]) -> Result<(), ConfigError>;
+    fn get_config(&self, key : &str) -> Result<ConfigValue, ConfigError>;
+    fn list_config(&self) -> Vec<ConfigEntry>;
     fn init(&mut self) -> i32;
     fn step(&mut self, steps: u64) -> i32;
+    fn step_blocking(&mut self, steps: u64, timeout : Option<Duration>) -> Option<SchedulerState>;
     fn pause(&mut self) -> i32;
     fn end(&mut self) -> i32;
+    fn shutdown(&mut self, timeout : Option<Duration>) -> i32;
+    fn reset(&mut self) -> i32;
 
     fn get_state(&self) -> SchedulerState;
+    fn get_faults(&self) -> Vec<ModelFault>;
+    fn list_channels(&self) -> Vec<ChannelInfo>;
+    fn set_channel_latency(&mut self, key : ChannelKey, latency : f64) -> Result<(), ChannelError>;
+    fn start_recording(&mut self, path : &str) -> std::io::Result<()>;
+    fn stop_recording(&mut self) -> std::io::Result<()>;
+    fn wait_for_state(&self, state : SchedulerState, timeout : Option<Duration>) -> Option<SchedulerState>;
 }
 
 pub struct ScheduledObject {
-    pub model : Box<dyn BaseModel + Send>,
+    pub id : ModelId,
+    // the outer box keeps the address handed back to the caller valid
+    // while the schedule is rearranged
+    pub model : Box<Box<dyn BaseModel + Send>>,
     pub divisor : i64,
     pub offset : i64,
 
     pub counter : i64,
+
+    // a disabled model keeps its place in the schedule but is not stepped
+    pub enabled : bool,
+    pub control : Arc<ModelControl>,
+}
+
+// Requests made against a scheduled model after it has been handed to a
+// worker thread. The scheduler writes these at any time, and the worker
+// applies them to its ScheduledObject at the start of a frame
+pub struct ModelControl {
+    pub enabled : AtomicBool,
+    pub divisor : AtomicI64,
+    pub offset  : AtomicI64,
+    pub rate_changed : AtomicBool,
+}
+
+impl ModelControl {
+    pub fn new(divisor : i64, offset : i64) -> ModelControl {
+        ModelControl {
+            enabled : AtomicBool::new(true),
+            divisor : AtomicI64::new(divisor),
+            offset  : AtomicI64::new(offset),
+            rate_changed : AtomicBool::new(false),
+        }
+    }
+}
+
+impl ScheduledObject {
+    // Copies pending requests into the object. `frame` is the number of
+    // frames the owning thread has executed, used to keep the phase of a
+    // new rate consistent with a model scheduled with it from the start
+    pub fn apply_requests(&mut self, frame : i64) {
+        self.enabled = self.control.enabled.load(Ordering::Acquire);
+        if self.control.rate_changed.swap(false, Ordering::AcqRel) {
+            self.divisor = self.control.divisor.load(Ordering::Acquire);
+            self.offset  = self.control.offset.load(Ordering::Acquire);
+            self.counter = (self.offset + frame) % self.divisor;
+        }
+    }
 }
@@ -0,0 +1,181 @@
+
+// Shared memory signal export. Selected signals are copied into a POSIX
+// shared memory segment at the end of every frame of one thread, so that
+// plotting tools and HMIs on the same machine can watch them without going
+// through Julia. The segment starts with a SegmentHeader, followed by one
+// SignalEntry per signal, followed by the signal data. Readers follow the
+// seqlock protocol: read seq, copy the data, then read seq again, retrying
+// if either value was odd or they differ
+
+use serde::Deserialize;
+use std::ffi::CString;
+use std::io;
+use std::mem::size_of;
+use std::ptr;
+use std::sync::atomic::{AtomicU64, Ordering, fence};
+
+use rsisappinterface::Framework;
+use rsisappinterface::RuntimeStatus;
+
+use crate::hooks::FrameHook;
+
+const MAGIC : &[u8; 8] = b"RSISSHM1";
+const NAME_LEN : usize = 64;
+const ELEMENT_LEN : usize = 16;
+
+#[repr(C)]
+struct SegmentHeader {
+    magic : [u8; 8],
+    seq : AtomicU64, // odd while the data is being updated
+    tick : i64,      // sim tick the data was copied at
+    time : f64,
+    count : u64,      // signal entries following the header
+    entry_size : u64, // size of one SignalEntry
+    data_offset : u64,
+    data_size : u64,
+}
+
+#[repr(C)]
+struct SignalEntry {
+    name : [u8; NAME_LEN],       // nul terminated
+    element : [u8; ELEMENT_LEN], // nul terminated type name, e.g. "Float64"
+    offset : u64, // from the start of the segment
+    size : u64,   // in bytes
+}
+
+// A signal to export. The address is that of a model port, and must stay
+// valid for as long as the export exists
+#[derive(Clone,Debug,Deserialize)]
+pub struct ExportedSignal {
+    pub name : String,
+    pub element : String,
+    pub address : u64,
+    pub size : u64,
+}
+
+fn fixed<const N : usize>(text : &str) -> io::Result<[u8; N]> {
+    let mut out = [0u8; N];
+    if text.len() >= N || text.contains('\0') {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} does not fit in {} bytes", text, N - 1)));
+    }
+    out[..text.len()].copy_from_slice(text.as_bytes());
+    Ok(out)
+}
+
+fn align8(size : usize) -> usize {
+    (size + 7) & !7
+}
+
+pub struct SignalExport {
+    name : CString,
+    base : *mut u8,
+    len : usize,
+    sources : Vec<(*const u8, *mut u8, usize)>, // model memory, segment memory, bytes
+}
+
+unsafe impl Send for SignalExport {}
+
+impl SignalExport {
+    // Creates the segment, replacing any left behind by an earlier run.
+    // Names follow shm_open, and are prefixed with '/' if needed
+    pub fn create(segment : &str, signals : &[ExportedSignal]) -> io::Result<SignalExport> {
+        let segment = if segment.starts_with('/') { String::from(segment) } else { format!("/{}", segment) };
+        let name = CString::new(segment).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let mut entries = Vec::with_capacity(signals.len());
+        let data_offset = align8(size_of::<SegmentHeader>() + signals.len() * size_of::<SignalEntry>());
+        let mut offset = data_offset;
+        for signal in signals {
+            if signal.address == 0 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("signal {} has no address", signal.name)));
+            }
+            entries.push(SignalEntry {
+                name : fixed(&signal.name)?,
+                element : fixed(&signal.element)?,
+                offset : offset as u64,
+                size : signal.size,
+            });
+            offset = align8(offset + signal.size as usize);
+        }
+        let len = offset;
+        unsafe {
+            libc::shm_unlink(name.as_ptr());
+            let fd = libc::shm_open(name.as_ptr(), libc::O_CREAT | libc::O_EXCL | libc::O_RDWR, 0o644);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::ftruncate(fd, len as libc::off_t) != 0 {
+                let err = io::Error::last_os_error();
+                libc::close(fd);
+                libc::shm_unlink(name.as_ptr());
+                return Err(err);
+            }
+            let base = libc::mmap(ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED, fd, 0);
+            // the mapping keeps the segment open
+            libc::close(fd);
+            if base == libc::MAP_FAILED {
+                let err = io::Error::last_os_error();
+                libc::shm_unlink(name.as_ptr());
+                return Err(err);
+            }
+            let base = base as *mut u8;
+            ptr::write(base as *mut SegmentHeader, SegmentHeader {
+                magic : *MAGIC,
+                seq : AtomicU64::new(0),
+                tick : 0,
+                time : 0.0,
+                count : entries.len() as u64,
+                entry_size : size_of::<SignalEntry>() as u64,
+                data_offset : data_offset as u64,
+                data_size : (len - data_offset) as u64,
+            });
+            let table = base.add(size_of::<SegmentHeader>()) as *mut SignalEntry;
+            let mut sources = Vec::with_capacity(entries.len());
+            for (ii, entry) in entries.into_iter().enumerate() {
+                sources.push((signals[ii].address as *const u8, base.add(entry.offset as usize), entry.size as usize));
+                ptr::write(table.add(ii), entry);
+            }
+            Ok(SignalExport {
+                name : name,
+                base : base,
+                len : len,
+                sources : sources,
+            })
+        }
+    }
+
+    fn header(&self) -> *mut SegmentHeader {
+        self.base as *mut SegmentHeader
+    }
+
+    // Copies every signal into the segment
+    pub fn update(&mut self, tick : i64, time : f64) {
+        unsafe {
+            let header = self.header();
+            let seq = (*header).seq.load(Ordering::Relaxed);
+            (*header).seq.store(seq + 1, Ordering::Relaxed);
+            fence(Ordering::Release);
+            ptr::write_volatile(ptr::addr_of_mut!((*header).tick), tick);
+            ptr::write_volatile(ptr::addr_of_mut!((*header).time), time);
+            for (src, dst, size) in self.sources.iter() {
+                ptr::copy_nonoverlapping(*src, *dst, *size);
+            }
+            (*header).seq.store(seq + 2, Ordering::Release);
+        }
+    }
+}
+
+impl FrameHook for SignalExport {
+    fn frame_end(&mut self, interface : &mut Box<dyn Framework>) -> RuntimeStatus {
+        self.update(interface.get_simtick(), interface.get_simtime());
+        RuntimeStatus::OK
+    }
+}
+
+impl Drop for SignalExport {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.base as *mut libc::c_void, self.len);
+            libc::shm_unlink(self.name.as_ptr());
+        }
+    }
+}
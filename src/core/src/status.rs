@@ -0,0 +1,60 @@
+
+// Status codes returned through the C interface. Each source of errors owns
+// one range of codes, so that a code means the same thing whichever call
+// returned it. The frontend matches on these values, so ranges are only ever
+// added after the last one, and existing codes never move
+
+use std::ops::Range;
+
+pub const GENERAL  : Range<u32> = 0..3;   // RSISStat OK, BADARG, ERR
+pub const SCHEDULE : Range<u32> = 3..10;  // ScheduleError
+pub const TIMEOUT  : Range<u32> = 10..11; // RSISStat TIMEOUT
+pub const CONFIG   : Range<u32> = 11..14; // ConfigError
+pub const CHANNEL  : Range<u32> = 14..18; // ChannelError
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RSISStat;
+    use crate::channel::ChannelError;
+    use crate::config::ConfigError;
+    use crate::scheduler::ScheduleError;
+
+    #[test]
+    fn ranges_do_not_overlap() {
+        let ranges = [GENERAL, SCHEDULE, TIMEOUT, CONFIG, CHANNEL];
+        for (ii, a) in ranges.iter().enumerate() {
+            assert!(a.start < a.end);
+            for b in ranges[ii + 1..].iter() {
+                assert!(a.end <= b.start || b.end <= a.start, "{:?} overlaps {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn codes_stay_in_their_range() {
+        for stat in [RSISStat::OK, RSISStat::BADARG, RSISStat::ERR] {
+            assert!(GENERAL.contains(&(stat as u32)));
+        }
+        assert!(TIMEOUT.contains(&(RSISStat::TIMEOUT as u32)));
+        for err in [ScheduleError::BADTHREAD, ScheduleError::BADDIVISOR, ScheduleError::BADOFFSET, ScheduleError::NULLMODEL,
+                    ScheduleError::BADID, ScheduleError::BADINDEX, ScheduleError::BADSTATE] {
+            assert!(SCHEDULE.contains(&(err as u32)), "{:?}", err);
+        }
+        for err in [ConfigError::BADKEY, ConfigError::BADVALUE, ConfigError::BADSTATE] {
+            assert!(CONFIG.contains(&(err as u32)), "{:?}", err);
+        }
+        for err in [ChannelError::BADSCHEMA, ChannelError::BADDEPTH, ChannelError::BADKIND, ChannelError::BADLATENCY] {
+            assert!(CHANNEL.contains(&(err as u32)), "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn released_codes_are_unchanged() {
+        assert_eq!(ScheduleError::BADTHREAD as u32, 3);
+        assert_eq!(ScheduleError::BADSTATE as u32, 9);
+        assert_eq!(RSISStat::TIMEOUT as u32, 10);
+        assert_eq!(ConfigError::BADKEY as u32, 11);
+        assert_eq!(ChannelError::BADSCHEMA as u32, 14);
+    }
+}
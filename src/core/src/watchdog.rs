@@ -0,0 +1,219 @@
+
+// The watchdog catches model calls that never return. Each worker thread
+// marks the start and end of every model and hook call on its heartbeat, as
+// well as its own waits for other threads and for the clock, and the
+// watchdog thread reports a call that has been running for longer than the
+// deadline, since the scheduler would otherwise hang with no indication of
+// which model is stuck
+
+use crate::rsis::RunnerEvent;
+use crate::scheduler::{FaultKind, FaultLog, ModelFault, ModelId};
+
+use std::sync::{Arc, mpsc::Sender};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering, fence};
+use std::{thread, time};
+
+// What a worker thread is waiting on. Model and hook calls, and the waits of
+// the scheduler itself between them
+#[repr(u8)]
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum Phase {
+    INIT       = 1,
+    STEP       = 2,
+    PAUSE      = 3,
+    STOP       = 4,
+    FRAMESTART = 5,
+    FRAMEEND   = 6,
+    SYNC       = 7, // waiting for the other threads at a sync point
+    SLEEP      = 8, // sleeping out a paced frame
+}
+
+impl Phase {
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::INIT       => "init",
+            Phase::STEP       => "step",
+            Phase::PAUSE      => "pause",
+            Phase::STOP       => "stop",
+            Phase::FRAMESTART => "frame_start",
+            Phase::FRAMEEND   => "frame_end",
+            Phase::SYNC       => "sync",
+            Phase::SLEEP      => "sleep",
+        }
+    }
+
+    fn from_u8(value : u8) -> Option<Phase> {
+        match value {
+            1 => Some(Phase::INIT),
+            2 => Some(Phase::STEP),
+            3 => Some(Phase::PAUSE),
+            4 => Some(Phase::STOP),
+            5 => Some(Phase::FRAMESTART),
+            6 => Some(Phase::FRAMEEND),
+            7 => Some(Phase::SYNC),
+            8 => Some(Phase::SLEEP),
+            _ => None
+        }
+    }
+}
+
+// Bits of the packed call below the phase, which hold the model id
+const MODEL_BITS : u32 = 56;
+
+// Call in progress on a worker thread
+#[derive(Copy,Clone)]
+struct ActiveCall {
+    model : Option<ModelId>, // None for a frame hook or the scheduler
+    phase : Phase,
+    start : time::Instant, // pushed back by any allowance the call was given
+}
+
+// Written by one worker thread around every call, without locking. The
+// call is described before the sequence number is made odd, so the
+// watchdog can tell a consistent read from one that raced a new call
+pub struct Heartbeat {
+    epoch : time::Instant,
+    seq   : AtomicU64, // odd while in a call
+    call  : AtomicU64, // phase in the top bits, model id below, 0 for none
+    start : AtomicU64, // nanoseconds from epoch
+}
+
+impl Heartbeat {
+    pub fn new() -> Heartbeat {
+        Heartbeat {
+            epoch : time::Instant::now(),
+            seq   : AtomicU64::new(0),
+            call  : AtomicU64::new(0),
+            start : AtomicU64::new(0),
+        }
+    }
+
+    pub fn enter(&self, model : Option<ModelId>, phase : Phase) {
+        self.enter_with_allowance(model, phase, time::Duration::ZERO);
+    }
+
+    // The deadline only starts once `allowance` has passed, for waits that
+    // are expected to take that long
+    pub fn enter_with_allowance(&self, model : Option<ModelId>, phase : Phase, allowance : time::Duration) {
+        let start = (self.epoch.elapsed() + allowance).as_nanos() as u64;
+        let call = ((phase as u64) << MODEL_BITS) | model.unwrap_or(0);
+        fence(Ordering::Release);
+        self.call.store(call, Ordering::Relaxed);
+        self.start.store(start, Ordering::Relaxed);
+        self.seq.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn leave(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+    }
+
+    // The sequence number, and the call in progress if there is one
+    fn active(&self) -> (u64, Option<ActiveCall>) {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq % 2 == 0 {
+                return (seq, None);
+            }
+            let call = self.call.load(Ordering::Relaxed);
+            let start = self.start.load(Ordering::Relaxed);
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) != seq {
+                continue; // the call ended while being read
+            }
+            let model = call & ((1 << MODEL_BITS) - 1);
+            return (seq, Phase::from_u8((call >> MODEL_BITS) as u8).map(|phase| ActiveCall {
+                model : if model == 0 { None } else { Some(model) },
+                phase : phase,
+                start : self.epoch + time::Duration::from_nanos(start),
+            }));
+        }
+    }
+}
+
+pub struct Watchdog {
+    stop   : Arc<AtomicBool>,
+    handle : thread::JoinHandle<()>,
+}
+
+impl Watchdog {
+    // Starts checking the heartbeats, one per worker thread. The first call
+    // found over the deadline is recorded and reported to the runner, after
+    // which the watchdog exits
+    pub fn start(heartbeats : Vec<Arc<Heartbeat>>, deadline : time::Duration, faults : Arc<FaultLog>, tx : Sender<RunnerEvent>) -> Watchdog {
+        let stop = Arc::new(AtomicBool::new(false));
+        let cstop = Arc::clone(&stop);
+        let period = (deadline / 4).clamp(time::Duration::from_millis(1), time::Duration::from_millis(100));
+        let handle = thread::spawn(move|| {
+            let mut last_seqs = vec![0; heartbeats.len()];
+            while !cstop.load(Ordering::Acquire) {
+                thread::sleep(period);
+                let (seqs, calls) : (Vec<_>, Vec<_>) = heartbeats.iter().map(|heartbeat| heartbeat.active()).unzip();
+                let now = time::Instant::now();
+                for (pos, call) in calls.iter().enumerate() {
+                    let call = match call {
+                        Some(call) => call,
+                        None => continue
+                    };
+                    let elapsed = now.saturating_duration_since(call.start);
+                    if elapsed <= deadline {
+                        continue;
+                    }
+                    if call.phase == Phase::SYNC {
+                        // waiting on other threads is only a fault once none
+                        // of them can get any further
+                        let stuck = (0..calls.len()).filter(|other| *other != pos).all(|other| {
+                            seqs[other] == last_seqs[other] && !matches!(calls[other], Some(call) if call.phase != Phase::SYNC)
+                        });
+                        if !stuck {
+                            continue;
+                        }
+                    }
+                    faults.record(ModelFault {
+                        kind    : FaultKind::TIMEOUT,
+                        thread  : pos,
+                        model   : call.model,
+                        phase   : call.phase.name(),
+                        message : format!("no return after {:.3} [s]", elapsed.as_secs_f64()),
+                    });
+                    let _ = tx.send(RunnerEvent::TIMEOUT(pos));
+                    return;
+                }
+                last_seqs = seqs;
+            }
+        });
+        Watchdog {
+            stop   : stop,
+            handle : handle,
+        }
+    }
+
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::Release);
+        let _ = self.handle.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heartbeat_reports_the_call_in_progress() {
+        let heartbeat = Heartbeat::new();
+        assert!(heartbeat.active().1.is_none());
+        heartbeat.enter(Some(42), Phase::STEP);
+        let (seq, call) = heartbeat.active();
+        let call = call.unwrap();
+        assert_eq!((call.model, call.phase), (Some(42), Phase::STEP));
+        heartbeat.leave();
+        heartbeat.enter_with_allowance(None, Phase::SLEEP, time::Duration::from_secs(60));
+        let (next, call) = heartbeat.active();
+        let call = call.unwrap();
+        assert_eq!((call.model, call.phase), (None, Phase::SLEEP));
+        // the allowance holds off the deadline
+        assert!(call.start > time::Instant::now() + time::Duration::from_secs(30));
+        assert!(next > seq);
+        heartbeat.leave();
+        assert!(heartbeat.active().1.is_none());
+    }
+}